@@ -0,0 +1,391 @@
+use crate::error::Error;
+use object::Object;
+use zydis::ffi::Decoder;
+use zydis::formatter::{Formatter, OutputBuffer};
+use zydis::{
+    enums::generated::{AddressWidth, FormatterStyle, MachineMode, Mnemonic, Register},
+    DecodedInstruction,
+};
+
+/// Where a call or jump instruction transfers control to
+#[derive(Debug, Clone, PartialEq)]
+pub enum BranchTarget {
+    /// Fixed address
+    Address(u64),
+    /// Address held in a register. Note: uses bpftrace register names, see
+    /// `Architecture::bpftrace_ip_register`.
+    Register(String),
+    /// Address loaded from memory at `register + displacement`. `address` is
+    /// the static address of the memory, if it can be computed (i.e. when it
+    /// is relative to the instruction pointer).
+    Memory {
+        register: String,
+        displacement: i64,
+        address: Option<u64>,
+    },
+    /// Target couldn't be determined
+    Unknown,
+}
+
+/// A call or jump instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub address: u64,
+    pub length: u8,
+    pub target: BranchTarget,
+}
+
+/// Instruction analysis for a particular architecture. All functions take
+/// machine code `code`, starting at `address`.
+pub trait Architecture: Sync {
+    /// Call instructions in `code`
+    fn calls(&self, address: u64, code: &[u8]) -> Vec<Branch>;
+
+    /// Unconditional direct or memory indirect jump instructions in `code`
+    fn jumps(&self, address: u64, code: &[u8]) -> Vec<Branch>;
+
+    /// (entry address, GOT entry address) of each entry in PLT section
+    /// `code`
+    fn plt_entries(&self, address: u64, code: &[u8]) -> Vec<(u64, u64)>;
+
+    /// Name of the instruction pointer in bpftrace's `reg()` builtin
+    fn bpftrace_ip_register(&self) -> &'static str;
+}
+
+/// Returns the architecture of `file`, if it is supported
+pub fn for_file(file: &object::File) -> Result<&'static dyn Architecture, Error> {
+    match file.architecture() {
+        object::Architecture::X86_64 => Ok(&X86_64),
+        object::Architecture::Aarch64 => Ok(&Aarch64),
+        architecture => Err(format!("Unsupported architecture {:?}", architecture).into()),
+    }
+}
+
+pub struct X86_64;
+
+impl X86_64 {
+    fn branches(&self, address: u64, code: &[u8], mnemonic: Mnemonic) -> Vec<Branch> {
+        let decoder = Decoder::new(MachineMode::LONG_64, AddressWidth::_64).unwrap();
+        get_instructions_with_mnemonic(&decoder, address, code, mnemonic)
+            .map(|(instruction, ip)| {
+                assert!(instruction.operand_count > 0);
+                let operand = &instruction.operands[0];
+                let target = match (operand.reg, operand.mem.base) {
+                    // Indexed memory (e.g. `[rax+rcx*8+0x10]`) can't be
+                    // expressed as a register and displacement
+                    (Register::NONE, _) if operand.mem.index != Register::NONE => {
+                        BranchTarget::Unknown
+                    }
+                    (Register::NONE, Register::NONE) => {
+                        match instruction.calc_absolute_address(ip, operand) {
+                            Ok(address) => BranchTarget::Address(address),
+                            Err(_) => BranchTarget::Unknown,
+                        }
+                    }
+                    (Register::NONE, base) => BranchTarget::Memory {
+                        register: x86_bpftrace_register(base),
+                        displacement: operand.mem.disp.displacement,
+                        address: match base {
+                            Register::RIP => instruction.calc_absolute_address(ip, operand).ok(),
+                            _ => None,
+                        },
+                    },
+                    (register, _) => BranchTarget::Register(x86_bpftrace_register(register)),
+                };
+                Branch {
+                    address: ip,
+                    length: instruction.length,
+                    target,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Architecture for X86_64 {
+    fn calls(&self, address: u64, code: &[u8]) -> Vec<Branch> {
+        self.branches(address, code, Mnemonic::CALL)
+    }
+
+    fn jumps(&self, address: u64, code: &[u8]) -> Vec<Branch> {
+        self.branches(address, code, Mnemonic::JMP)
+            .into_iter()
+            .filter(|branch| !matches!(branch.target, BranchTarget::Register(_)))
+            .collect()
+    }
+
+    fn plt_entries(&self, address: u64, code: &[u8]) -> Vec<(u64, u64)> {
+        // Each entry jumps through its GOT entry
+        self.jumps(address, code)
+            .into_iter()
+            .filter_map(|branch| match branch.target {
+                BranchTarget::Address(target) => Some((branch.address, target)),
+                BranchTarget::Memory {
+                    address: Some(target),
+                    ..
+                } => Some((branch.address, target)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn bpftrace_ip_register(&self) -> &'static str {
+        "ip"
+    }
+}
+
+/// Name of `register` in bpftrace's `reg()` builtin
+/// (https://github.com/iovisor/bpftrace/blob/master/src/arch/x86_64.cpp), which
+/// notably does not have E or R prefixes.
+fn x86_bpftrace_register(register: Register) -> String {
+    let name = register.get_string().unwrap();
+    match register {
+        Register::RAX
+        | Register::RBX
+        | Register::RCX
+        | Register::RDX
+        | Register::RSI
+        | Register::RDI
+        | Register::RBP
+        | Register::RSP
+        | Register::RIP => name[1..].to_string(),
+        _ => name.to_string(),
+    }
+}
+
+fn get_instructions_with_mnemonic<'a, 'b>(
+    decoder: &'a Decoder,
+    start_address: u64,
+    code: &'b [u8],
+    mnemonic: Mnemonic,
+) -> CallIterator<'a, 'b> {
+    CallIterator {
+        it: decoder.instruction_iterator(code, start_address),
+        mnemonic,
+    }
+}
+
+struct CallIterator<'a, 'b> {
+    it: zydis::InstructionIterator<'a, 'b>,
+    mnemonic: Mnemonic,
+}
+
+impl Iterator for CallIterator<'_, '_> {
+    type Item = (DecodedInstruction, u64);
+
+    fn next(&mut self) -> Option<(DecodedInstruction, u64)> {
+        for (instruction, ip) in self.it.by_ref() {
+            if instruction.mnemonic == self.mnemonic {
+                if log::log_enabled!(log::Level::Trace) {
+                    let formatter = Formatter::new(FormatterStyle::INTEL)
+                        .expect("Could not create zydis Formatter");
+                    let mut buffer = [0u8; 200];
+                    let mut buffer = OutputBuffer::new(&mut buffer[..]);
+                    formatter
+                        .format_instruction(&instruction, &mut buffer, Some(ip), None)
+                        .unwrap();
+                    log::trace!("{} 0x{:016X} {}", instruction.operand_count, ip, buffer);
+                }
+
+                return Some((instruction, ip));
+            }
+        }
+        None
+    }
+}
+
+/// AArch64 (little endian). All instructions are 4 bytes, so only the few
+/// instructions we care about are decoded.
+pub struct Aarch64;
+
+impl Aarch64 {
+    /// (address, instruction) of each instruction in `code`
+    fn instructions(address: u64, code: &[u8]) -> impl Iterator<Item = (u64, u32)> + '_ {
+        code.chunks_exact(4).enumerate().map(move |(i, bytes)| {
+            (
+                address + 4 * i as u64,
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            )
+        })
+    }
+
+    /// Target of B/BL instruction `instruction` at `address`
+    fn branch_target(address: u64, instruction: u32) -> u64 {
+        let offset = sign_extend(instruction & 0x03ff_ffff, 26) << 2;
+        address.wrapping_add(offset as u64)
+    }
+}
+
+impl Architecture for Aarch64 {
+    fn calls(&self, address: u64, code: &[u8]) -> Vec<Branch> {
+        Aarch64::instructions(address, code)
+            .filter_map(|(ip, instruction)| {
+                let target = if instruction & 0xfc00_0000 == 0x9400_0000 {
+                    // BL <label>
+                    BranchTarget::Address(Aarch64::branch_target(ip, instruction))
+                } else if instruction & 0xffff_fc1f == 0xd63f_0000 {
+                    // BLR <Xn>
+                    BranchTarget::Register(format!("r{}", (instruction >> 5) & 0x1f))
+                } else {
+                    return None;
+                };
+                Some(Branch {
+                    address: ip,
+                    length: 4,
+                    target,
+                })
+            })
+            .collect()
+    }
+
+    fn jumps(&self, address: u64, code: &[u8]) -> Vec<Branch> {
+        Aarch64::instructions(address, code)
+            // B <label>
+            .filter(|(_, instruction)| instruction & 0xfc00_0000 == 0x1400_0000)
+            .map(|(ip, instruction)| Branch {
+                address: ip,
+                length: 4,
+                target: BranchTarget::Address(Aarch64::branch_target(ip, instruction)),
+            })
+            .collect()
+    }
+
+    fn plt_entries(&self, address: u64, code: &[u8]) -> Vec<(u64, u64)> {
+        // Each entry is of the form
+        //   adrp x16, <GOT entry page>
+        //   ldr x17, [x16, <GOT entry page offset>]
+        //   add x16, x16, <GOT entry page offset>
+        //   br x17
+        // PLT0 has the same sequence, but after a `stp`, and jumps through a
+        // GOT entry without a relocation.
+        let instructions: Vec<(u64, u32)> = Aarch64::instructions(address, code).collect();
+        instructions
+            .windows(2)
+            .filter_map(|window| {
+                let (ip, adrp) = window[0];
+                let (_, ldr) = window[1];
+                // ADRP x16
+                if adrp & 0x9f00_001f != 0x9000_0010 {
+                    return None;
+                }
+                // LDR x17, [x16, #imm] (64-bit, unsigned offset)
+                if ldr & 0xffc0_03ff != 0xf940_0211 {
+                    return None;
+                }
+                let pages = sign_extend(((adrp >> 3) & 0x001f_fffc) | ((adrp >> 29) & 0x3), 21);
+                let page = (ip & !0xfff).wrapping_add((pages << 12) as u64);
+                let offset = ((ldr >> 10) & 0xfff) as u64 * 8;
+                Some((ip, page + offset))
+            })
+            .collect()
+    }
+
+    fn bpftrace_ip_register(&self) -> &'static str {
+        "pc"
+    }
+}
+
+/// Sign extend the lowest `bits` bits of `value`
+fn sign_extend(value: u32, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value as i64) << shift) >> shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_bytes(instructions: &[u32]) -> Vec<u8> {
+        instructions.iter().flat_map(|i| i.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_x86_64_memory_calls() {
+        let code = [
+            0xff, 0x50, 0x10, // call qword ptr [rax+0x10]
+            0xff, 0x54, 0xc8, 0x10, // call qword ptr [rax+rcx*8+0x10]
+        ];
+        assert_eq!(
+            X86_64.calls(0x1000, &code),
+            vec![
+                Branch {
+                    address: 0x1000,
+                    length: 3,
+                    target: BranchTarget::Memory {
+                        register: "ax".to_string(),
+                        displacement: 0x10,
+                        address: None,
+                    },
+                },
+                Branch {
+                    address: 0x1003,
+                    length: 4,
+                    target: BranchTarget::Unknown,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aarch64_calls() {
+        let code = to_bytes(&[
+            0x9400_0004, // bl 0x1010
+            0xd63f_0100, // blr x8
+            0x17ff_fffe, // b 0x1000
+            0xd503_201f, // nop
+            0x97ff_fffc, // bl 0x1000
+        ]);
+        assert_eq!(
+            Aarch64.calls(0x1000, &code),
+            vec![
+                Branch {
+                    address: 0x1000,
+                    length: 4,
+                    target: BranchTarget::Address(0x1010),
+                },
+                Branch {
+                    address: 0x1004,
+                    length: 4,
+                    target: BranchTarget::Register("r8".to_string()),
+                },
+                Branch {
+                    address: 0x1010,
+                    length: 4,
+                    target: BranchTarget::Address(0x1000),
+                },
+            ]
+        );
+        assert_eq!(
+            Aarch64.jumps(0x1000, &code),
+            vec![Branch {
+                address: 0x1008,
+                length: 4,
+                target: BranchTarget::Address(0x1000),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_aarch64_plt_entries() {
+        let code = to_bytes(&[
+            // PLT0
+            0xa9bf_7bf0, // stp x16, x30, [sp, #-16]!
+            0xb000_0090, // adrp x16, 0x12000
+            0xf940_0611, // ldr x17, [x16, #8]
+            0x9100_2210, // add x16, x16, #8
+            0xd61f_0220, // br x17
+            0xd503_201f, // nop
+            0xd503_201f, // nop
+            0xd503_201f, // nop
+            // Entry
+            0xb000_0090, // adrp x16, 0x12000
+            0xf940_1611, // ldr x17, [x16, #40]
+            0x9100_a210, // add x16, x16, #40
+            0xd61f_0220, // br x17
+        ]);
+        assert_eq!(
+            Aarch64.plt_entries(0x1000, &code),
+            vec![(0x1004, 0x12008), (0x1020, 0x12028)]
+        );
+    }
+}
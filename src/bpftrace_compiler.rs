@@ -14,18 +14,23 @@ pub struct Block {
     expressions: Vec<Expression>,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub enum BlockType {
     Begin,
+    End,
     /// Rate in seconds
     Interval {
         rate_seconds: i32,
     },
-    Uprobe(FunctionName),
-    UprobeOffset(FunctionName, u32),
-    Uretprobe(FunctionName),
+    /// The first field of uprobes is the path of the object file (program or
+    /// shared library) containing the function. Uprobes and uretprobes can
+    /// attach to multiple functions, sharing the same block.
+    Uprobe(String, Vec<FunctionName>),
+    UprobeOffset(String, FunctionName, u32),
+    Uretprobe(String, Vec<FunctionName>),
 }
 
+#[derive(Clone)]
 pub enum Expression {
     /// Expression (without terminating semicolon)
     RawExpr(String),
@@ -54,12 +59,22 @@ impl BpftraceProgram {
         self.blocks.iter_mut()
     }
 
-    pub fn compile(&self, program_path: &str) -> String {
+    /// Whether any function is probed by an address that isn't in the symbol
+    /// table (see `FunctionName::probe_target`), which bpftrace refuses to
+    /// attach to unless run with `--unsafe`.
+    pub fn requires_unsafe(&self) -> bool {
+        self.blocks.iter().any(|block| match &block.block_type {
+            BlockType::Uprobe(_, functions) | BlockType::Uretprobe(_, functions) => {
+                functions.iter().any(|f| !f.is_in_symbol_table())
+            }
+            BlockType::UprobeOffset(_, function, _) => !function.is_in_symbol_table(),
+            _ => false,
+        })
+    }
+
+    pub fn compile(&self) -> String {
         // TODO add tests, show examples
-        self.blocks
-            .iter()
-            .map(|b| b.compile(program_path))
-            .join(" ")
+        self.blocks.iter().map(|b| b.compile()).join(" ")
     }
 }
 
@@ -75,8 +90,8 @@ impl Block {
         }
     }
 
-    pub fn get_type(&self) -> BlockType {
-        self.block_type
+    pub fn get_type(&self) -> &BlockType {
+        &self.block_type
     }
 
     pub fn add(&mut self, expression: Expression) {
@@ -95,19 +110,26 @@ impl Block {
         );
     }
 
-    pub fn compile(&self, program_path: &str) -> String {
+    pub fn compile(&self) -> String {
         let mut out = String::new();
-        match self.block_type {
+        match &self.block_type {
             BlockType::Begin => out += "BEGIN",
+            BlockType::End => out += "END",
             BlockType::Interval { rate_seconds } => out += &format!("interval:s:{}", rate_seconds),
-            BlockType::Uprobe(function) => {
-                out += &format!("uprobe:{}:{:?}", program_path, function)
+            BlockType::Uprobe(path, functions) => {
+                out += &functions
+                    .iter()
+                    .map(|f| format!("uprobe:{}:{}", path, f.probe_target(0)))
+                    .join(", ")
             }
-            BlockType::UprobeOffset(function, offset) => {
-                out += &format!("uprobe:{}:{:?}+{}", program_path, function, offset)
+            BlockType::UprobeOffset(path, function, offset) => {
+                out += &format!("uprobe:{}:{}", path, function.probe_target(*offset))
             }
-            BlockType::Uretprobe(function) => {
-                out += &format!("uretprobe:{}:{:?}", program_path, function)
+            BlockType::Uretprobe(path, functions) => {
+                out += &functions
+                    .iter()
+                    .map(|f| format!("uretprobe:{}:{}", path, f.probe_target(0)))
+                    .join(", ")
             }
         };
         if let Some(filter) = &self.filter {
@@ -0,0 +1,189 @@
+use object::Object;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Bump whenever the format of any cached data changes
+const CACHE_VERSION: u32 = 3;
+
+/// On-disk cache of data derived from a program that is expensive to compute,
+/// stored in `~/.cache/wachy/<build-id>/` (or under `$XDG_CACHE_HOME`).
+/// Entries are invalidated when the program's modification time changes, or
+/// when a different (or modified) separate debug file is used.
+pub struct Cache {
+    dir: PathBuf,
+    mtime: u64,
+    /// Path and modification time of the separate debug file, if any
+    debug_file: Option<(String, u64)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry<T> {
+    version: u32,
+    mtime: u64,
+    debug_file: Option<(String, u64)>,
+    value: T,
+}
+
+fn modification_time<P: AsRef<Path>>(path: P) -> Option<u64> {
+    Some(
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_secs(),
+    )
+}
+
+impl Cache {
+    /// `debug_file_path` is the separate debug file that symbols and line
+    /// info were read from, if any. Returns None if the program has no build
+    /// ID or the cache directory can't be determined.
+    pub fn new(
+        file: &object::File,
+        file_path: &str,
+        debug_file_path: Option<&str>,
+    ) -> Option<Cache> {
+        let build_id = file.build_id().ok()??;
+        let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+        let mtime = modification_time(file_path)?;
+        let debug_file = match debug_file_path {
+            Some(path) => Some((path.to_string(), modification_time(path)?)),
+            None => None,
+        };
+        let cache_home = match std::env::var_os("XDG_CACHE_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+        };
+        Some(Cache {
+            dir: cache_home.join("wachy").join(hex),
+            mtime,
+            debug_file,
+        })
+    }
+
+    /// Returns the cached value `name`, if it exists and is valid
+    pub fn read<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        let path = self.dir.join(format!("{}.json", name));
+        let contents = std::fs::read(&path).ok()?;
+        match serde_json::from_slice::<Entry<T>>(&contents) {
+            Ok(entry)
+                if entry.version == CACHE_VERSION
+                    && entry.mtime == self.mtime
+                    && entry.debug_file == self.debug_file =>
+            {
+                log::info!("Using cached {} from {}", name, path.display());
+                Some(entry.value)
+            }
+            Ok(_) => {
+                log::info!("Ignoring outdated cache file {}", path.display());
+                None
+            }
+            Err(err) => {
+                log::warn!("Ignoring invalid cache file {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    /// Cache `value` as `name`. Failures are only logged, since the cache is
+    /// an optimization.
+    pub fn write<T: Serialize>(&self, name: &str, value: &T) {
+        let path = self.dir.join(format!("{}.json", name));
+        let entry = Entry {
+            version: CACHE_VERSION,
+            mtime: self.mtime,
+            debug_file: self.debug_file.clone(),
+            value,
+        };
+        let result = std::fs::create_dir_all(&self.dir)
+            .map_err(|err| err.to_string())
+            .and_then(|_| serde_json::to_vec(&entry).map_err(|err| err.to_string()))
+            .and_then(|contents| {
+                // Write to a temporary file first so that concurrent readers
+                // never see a partially written file
+                let tmp_path = self
+                    .dir
+                    .join(format!("{}.json.{}", name, std::process::id()));
+                std::fs::write(&tmp_path, contents)
+                    .and_then(|_| std::fs::rename(&tmp_path, &path))
+                    .map_err(|err| err.to_string())
+            });
+        match result {
+            Ok(()) => log::info!("Cached {} in {}", name, path.display()),
+            Err(err) => log::warn!("Failed to write cache file {}: {}", path.display(), err),
+        }
+    }
+}
+
+/// Point the cache at a temporary directory, so that tests don't write to the
+/// user's cache
+#[cfg(test)]
+pub fn use_test_cache_home() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("wachy_test_cache_{}", std::process::id()));
+        std::env::set_var("XDG_CACHE_HOME", dir);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn set_mtime(path: &Path, time: SystemTime) {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_read_write() {
+        use_test_cache_home();
+        // Copy the program, since modification times are changed below
+        let dir =
+            std::env::temp_dir().join(format!("wachy_test_cache_files_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program_path = dir.join("inline");
+        let debug_path = dir.join("inline.debug");
+        let other_debug_path = dir.join("other.debug");
+        for path in &[&program_path, &debug_path, &other_debug_path] {
+            std::fs::copy("testdata/inline", path).unwrap();
+        }
+        let program_path = program_path.to_str().unwrap();
+        let debug_path = debug_path.to_str().unwrap();
+        let other_debug_path = other_debug_path.to_str().unwrap();
+        let data = std::fs::read(program_path).unwrap();
+        let file = object::File::parse(&*data).unwrap();
+        let cache = || Cache::new(&file, program_path, Some(debug_path)).unwrap();
+
+        assert_eq!(cache().read::<Vec<u32>>("test"), None);
+        cache().write("test", &vec![1u32, 2]);
+        assert_eq!(cache().read::<Vec<u32>>("test"), Some(vec![1, 2]));
+
+        // Different debug file
+        let without_debug_file = Cache::new(&file, program_path, None).unwrap();
+        assert_eq!(without_debug_file.read::<Vec<u32>>("test"), None);
+        let other_debug_file = Cache::new(&file, program_path, Some(other_debug_path)).unwrap();
+        assert_eq!(other_debug_file.read::<Vec<u32>>("test"), None);
+
+        // Modified debug file
+        let time = SystemTime::now() + Duration::from_secs(10);
+        set_mtime(Path::new(debug_path), time);
+        assert_eq!(cache().read::<Vec<u32>>("test"), None);
+        cache().write("test", &vec![3u32]);
+        assert_eq!(cache().read::<Vec<u32>>("test"), Some(vec![3]));
+
+        // Modified program
+        set_mtime(Path::new(program_path), time);
+        assert_eq!(cache().read::<Vec<u32>>("test"), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,58 +1,92 @@
+use crate::arch::BranchTarget;
 use crate::error::Error;
 use crate::events;
 use crate::events::{Event, TraceInfoMode};
+use crate::history::History;
+use crate::linker;
 use crate::program;
-use crate::program::{FunctionName, Program};
+use crate::program::{FunctionName, LoadProgress, Program, SymbolsGenerator};
 use crate::search;
 use crate::search::Searcher;
-use crate::trace_structs::{CallInstruction, FrameInfo, InstructionType, TraceMode, TraceStack};
+use crate::source::Sources;
+use crate::symbol_tree::SymbolTree;
+use crate::trace_structs::{
+    CallInstruction, FrameInfo, InstructionType, TraceMode, TraceStack, TraceTarget,
+};
 use crate::tracer::Tracer;
 use crate::views;
 use crate::views::TraceState;
 use cursive::traits::{Nameable, Resizable};
-use cursive::views::{Dialog, LinearLayout};
+use cursive::views::{Dialog, EditView, LinearLayout};
 use cursive::{Cursive, CursiveRunnable, CursiveRunner};
 use program::SymbolInfo;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::io::BufRead;
+use std::fmt;
+use std::iter;
+use std::rc::Rc;
 use std::sync::{mpsc, Arc};
+use std::thread;
 use std::time::{Duration, Instant};
-use zydis::enums::generated::{Mnemonic, Register};
 
 pub struct Controller {
     program: Program,
+    /// Shared libraries that frames have been pushed from, keyed by path
+    libraries: HashMap<String, Program>,
+    sources: Sources,
     searcher: Searcher,
     tracer: Tracer,
     trace_stack: Arc<TraceStack>,
     key_handler: KeyHandler,
+    /// Built in the background the first time it is browsed
+    symbol_tree: Option<Rc<SymbolTree>>,
+    /// Whether `symbol_tree` is being built in the background
+    is_building_symbol_tree: bool,
+    history: History,
+    /// Whether the callers index is being built in the background
+    is_indexing_calls: bool,
+    tx: mpsc::Sender<Event>,
 }
 
 impl Controller {
     /// For initial function, display searching UI after this many milliseconds
     const DISPLAY_SEARCHING_UI_MS: u128 = 100;
+    const INDIRECT_TARGETS_TITLE: &'static str = "Select the call target to enter";
 
-    pub fn run(program: Program, search: &str) -> Result<(), Error> {
+    /// Loads the program at `file_path` on a background thread while the
+    /// initial function is being selected.
+    pub fn run(
+        file_path: String,
+        debug_dirs: Vec<String>,
+        sources: Sources,
+        search: &str,
+        target: TraceTarget,
+    ) -> Result<(), Error> {
         Tracer::run_prechecks()?;
 
         let (tx, rx) = mpsc::channel();
         let mut siv = cursive::default().into_runner();
-        let function = Controller::get_initial_function(
+        let mut history = History::new(&file_path);
+        let result = Controller::get_initial_function(
             search,
             &mut siv,
-            Searcher::new(tx.clone(), program.symbols_generator()),
+            file_path,
+            debug_dirs,
+            &history,
             tx.clone(),
             &rx,
         )?;
-        let function = match function {
-            Some(f) => f,
+        let (program, function) = match result {
+            Some(r) => r,
             None => return Ok(()),
         };
+        history.add_recent(function, &program.symbols_generator());
 
         let mut sview = views::new_source_view();
         let mut fview = views::new_footer_view();
-        let frame_info = Controller::setup_function(&program, function, &mut sview, &mut fview)?;
+        let frame_info =
+            Controller::setup_function(&program, &sources, function, &mut sview, &mut fview)?;
         siv.add_fullscreen_layer(
             cursive::views::Dialog::around(
                 LinearLayout::vertical()
@@ -65,19 +99,28 @@ impl Controller {
 
         let trace_stack = Arc::new(TraceStack::new(
             program.file_path.clone(),
+            program.arch().bpftrace_ip_register(),
+            target,
             frame_info,
             tx.clone(),
         ));
         let tracer = Tracer::new(Arc::clone(&trace_stack), tx.clone())?;
 
-        let searcher = Searcher::new(tx, program.symbols_generator());
+        let searcher = Searcher::new(tx.clone(), program.symbols_generator());
         Controller::add_callbacks(&mut siv);
         let controller = Controller {
             program,
+            libraries: HashMap::new(),
+            sources,
             searcher,
             tracer,
             trace_stack,
             key_handler: KeyHandler::new(),
+            symbol_tree: None,
+            is_building_symbol_tree: false,
+            history,
+            is_indexing_calls: false,
+            tx,
         };
         siv.set_user_data(controller);
 
@@ -96,76 +139,159 @@ impl Controller {
         Ok(())
     }
 
+    /// Returns the loaded program and the function selected by the user.
+    /// Searching starts as soon as the program's symbols have been read,
+    /// before its debug info is loaded.
     fn get_initial_function(
         search: &str,
         siv: &mut CursiveRunner<CursiveRunnable>,
-        searcher: Searcher,
+        file_path: String,
+        debug_dirs: Vec<String>,
+        history: &History,
         tx: mpsc::Sender<Event>,
         rx: &mpsc::Receiver<Event>,
-    ) -> Result<Option<FunctionName>, Error> {
-        let empty_search_results = vec![(
-            "Type to select the top-level function to trace".to_string(),
-            None,
-        )];
-        searcher.setup_search(empty_search_results, Vec::new());
+    ) -> Result<Option<(Program, FunctionName)>, Error> {
+        const TITLE: &str = "Select the top-level function to trace";
+        const HINT: &str = "Type to select the top-level function to trace, or enter a source location (file:line or directory)";
+        siv.add_layer(views::new_progress_view(
+            &format!("Loading {}", file_path),
+            "Reading program",
+        ));
+        let loader_tx = tx.clone();
+        thread::spawn(move || {
+            // Sending fails if we are exiting (i.e. the user quit before the
+            // program was loaded), so errors are ignored.
+            let object_path = file_path.clone();
+            let result = Program::load(file_path, debug_dirs, |progress| {
+                let _ = loader_tx.send(Event::LoadProgress(progress));
+            });
+            // Reading all line tables can take a while for large programs,
+            // so this is only done once the program can be used
+            let indexer = result.as_ref().ok().and_then(|p| p.line_indexer());
+            let _ = loader_tx.send(Event::ProgramLoaded(result.map(Box::new)));
+            if let Some(indexer) = indexer {
+                let line_index = indexer.run();
+                let _ = loader_tx.send(Event::LineTablesIndexed {
+                    object_path,
+                    line_index,
+                });
+            }
+        });
+
+        let searcher = Searcher::new(tx.clone(), SymbolsGenerator::default());
+        searcher.setup_search(vec![(HINT.to_string(), None)], Vec::new(), true);
         siv.set_user_data(searcher);
-        let search_view = views::new_search_view(
-            "Select the top-level function to trace",
-            vec![("Searching...".to_string(), None)],
-            move |siv: &mut Cursive, view_name: &str, search: &str, n_results: usize| {
-                let searcher = siv
-                    .user_data::<Searcher>()
-                    .expect("Bug: Searcher does not exist");
-                searcher.search(view_name, search, n_results);
-            },
-            move |_, symbol: &SymbolInfo| {
-                // TODO cancel any pending searches
-                tx.send(Event::SelectedFunction(symbol.name)).unwrap();
-            },
-        );
-        siv.add_layer(search_view);
-        // TODO pass name more cleanly
-        let callback = siv
-            .find_name::<cursive::views::EditView>("search_Select the top-level function to trace")
-            .unwrap()
-            .set_content(search);
-        callback(siv);
 
+        let mut program = None;
+        let mut selected_function = None;
+        let mut is_search_view_shown = false;
         let mut is_initial_result = true;
+        let mut is_line_index_built = false;
         let mut start_time = Some(Instant::now());
         while siv.is_running() {
             siv.step();
             match rx.try_recv() {
                 Ok(data) => match data {
+                    Event::LoadProgress(LoadProgress::Stage(stage)) => {
+                        views::set_progress_view(siv, stage);
+                    }
+                    Event::LoadProgress(LoadProgress::Symbols(symbols)) => {
+                        let empty_search_results =
+                            Controller::default_search_results(history, &symbols, HINT);
+                        let searcher = Controller::searcher(siv);
+                        searcher.setup_search(empty_search_results, Vec::new(), true);
+                        searcher.set_symbols(symbols);
+                        if is_search_view_shown {
+                            Controller::rerun_search(siv, TITLE);
+                        } else {
+                            is_search_view_shown = true;
+                            let tx = tx.clone();
+                            let search_view = views::new_search_view(
+                                TITLE,
+                                vec![("Searching...".to_string(), None)],
+                                move |siv: &mut Cursive,
+                                      view_name: &str,
+                                      search: &str,
+                                      n_results: usize| {
+                                    Controller::searcher(siv).search(view_name, search, n_results);
+                                },
+                                move |_, symbol: &SymbolInfo| {
+                                    // TODO cancel any pending searches
+                                    tx.send(Event::SelectedFunction(symbol.name)).unwrap();
+                                },
+                            );
+                            siv.add_layer(search_view);
+                            // TODO pass name more cleanly
+                            let callback = siv
+                                .find_name::<cursive::views::EditView>(&format!("search_{}", TITLE))
+                                .unwrap()
+                                .set_content(search);
+                            callback(siv);
+                        }
+                    }
+                    Event::ProgramLoaded(result) => {
+                        let loaded_program = *result?;
+                        let symbols = loaded_program.symbols_generator();
+                        program = Some(loaded_program);
+                        let empty_search_results =
+                            Controller::default_search_results(history, &symbols, HINT);
+                        let searcher = Controller::searcher(siv);
+                        searcher.setup_search(empty_search_results, Vec::new(), true);
+                        searcher.set_symbols(symbols);
+                        // Results may now include functions only described in
+                        // debug info
+                        if is_search_view_shown && selected_function.is_none() {
+                            Controller::rerun_search(siv, TITLE);
+                        }
+                    }
+                    Event::LineTablesIndexed { line_index, .. } => {
+                        is_line_index_built = true;
+                        let loaded_program = program
+                            .as_mut()
+                            .expect("Bug: line tables indexed before program loaded");
+                        loaded_program.set_line_index(line_index);
+                        Controller::searcher(siv).set_symbols(loaded_program.symbols_generator());
+                        // Source locations can now be searched
+                        if is_search_view_shown && selected_function.is_none() {
+                            Controller::rerun_search(siv, TITLE);
+                        }
+                    }
                     Event::SearchResults {
                         counter,
                         view_name,
                         results,
                     } => {
                         let was_initial_result = is_initial_result;
-                        is_initial_result = false;
-                        if !siv
-                            .user_data::<Searcher>()
-                            .expect("Bug: Searcher does not exist")
-                            .is_counter_current(counter)
+                        // Results without any functions (e.g. a source location
+                        // before the line tables have been indexed) don't count
+                        // as the initial result, so it is retried once the
+                        // program has loaded and its line tables have been
+                        // indexed
+                        is_initial_result = was_initial_result
+                            && !is_line_index_built
+                            && results.iter().all(|(_, symbol)| symbol.is_none());
+                        if selected_function.is_some()
+                            || !Controller::searcher(siv).is_counter_current(counter)
                         {
                             continue;
                         }
                         // If this was the initial search and there's only one
                         // match, consider this to be the selected one.
-                        if results.len() == 1 && was_initial_result {
+                        if results.len() == 1 && was_initial_result && !search.is_empty() {
                             if let Some(symbol) = &results[0].1 {
                                 siv.pop_layer();
-                                return Ok(Some(symbol.name));
+                                selected_function = Some(symbol.name);
                             };
                         }
-                        if views::update_search_view(siv, &view_name, results) {
+                        if selected_function.is_none()
+                            && views::update_search_view(siv, &view_name, results)
+                        {
                             siv.refresh();
                         }
                     }
                     Event::SelectedFunction(function) => {
-                        siv.pop_layer();
-                        return Ok(Some(function));
+                        // The search view has already been popped
+                        selected_function = Some(function);
                     }
                     _ => {
                         panic!("Bug: Unexpected event")
@@ -177,6 +303,17 @@ impl Controller {
                 Err(mpsc::TryRecvError::Empty) => (),
             }
 
+            if let (Some(loaded_program), Some(function)) = (&program, selected_function) {
+                // The function may have been selected before the program
+                // finished loading
+                let function = loaded_program.resolve_function(function).ok_or_else(|| {
+                    format!("Function {} not found after loading program", function)
+                })?;
+                // Progress view
+                siv.pop_layer();
+                return Ok(Some((program.take().unwrap(), function)));
+            }
+
             if start_time.map_or(false, |t| {
                 t.elapsed().as_millis() > Controller::DISPLAY_SEARCHING_UI_MS
             }) {
@@ -191,6 +328,37 @@ impl Controller {
         Ok(None)
     }
 
+    /// Pinned and recently traced functions if there are any, otherwise
+    /// `hint`
+    fn default_search_results(
+        history: &History,
+        symbols: &SymbolsGenerator,
+        hint: &str,
+    ) -> Vec<(String, Option<SymbolInfo>)> {
+        let results = history.search_results(symbols);
+        if results.is_empty() {
+            vec![(hint.to_string(), None)]
+        } else {
+            results
+        }
+    }
+
+    fn searcher(siv: &mut Cursive) -> &mut Searcher {
+        siv.user_data::<Searcher>()
+            .expect("Bug: Searcher does not exist")
+    }
+
+    /// Search again with the current contents of the search view `title`
+    fn rerun_search(siv: &mut Cursive, title: &str) {
+        let callback = siv
+            .call_on_name(&format!("search_{}", title), |view: &mut EditView| {
+                let content = view.get_content();
+                view.set_content(content.to_string())
+            })
+            .expect("Bug: Search view does not exist");
+        callback(siv);
+    }
+
     fn handle_event(siv: &mut CursiveRunner<CursiveRunnable>, event: Event) -> Result<(), Error> {
         let result = match event {
             Event::FatalTraceError { error_message } => {
@@ -295,14 +463,56 @@ impl Controller {
                             bview.set_content(text.join("\n"));
                         });
                     }
+                    TraceInfoMode::IndirectTargets(targets) => {
+                        // Don't overwrite results while the user is searching
+                        let is_searching = match siv.find_name::<cursive::views::EditView>(
+                            &format!("search_{}", Controller::INDIRECT_TARGETS_TITLE),
+                        ) {
+                            Some(edit_view) => !edit_view.get_content().is_empty(),
+                            None => false,
+                        };
+                        if !is_searching {
+                            let controller = siv
+                                .user_data::<Controller>()
+                                .expect("Bug: Controller does not exist");
+                            let results = controller.rank_indirect_targets(&targets);
+                            let symbols = results
+                                .iter()
+                                .filter_map(|(_, symbol)| symbol.clone())
+                                .collect();
+                            controller
+                                .searcher
+                                .setup_search(results.clone(), symbols, false);
+                            views::update_search_view(
+                                siv,
+                                &format!("select_{}", Controller::INDIRECT_TARGETS_TITLE),
+                                results,
+                            );
+                        }
+                    }
                 }
                 Ok(())
             }
-            Event::TraceCommandModified => {
-                siv.user_data::<Controller>()
+            Event::TracedProgramExited { counter } => {
+                let trace_stack = &siv
+                    .user_data::<Controller>()
                     .expect("Bug: Controller does not exist")
-                    .tracer
-                    .rerun_tracer();
+                    .trace_stack;
+                if !trace_stack.is_counter_current(counter) {
+                    return Ok(());
+                }
+                // Leave the final results displayed
+                let footer = format!(
+                    "{} | Program exited, press r to rerun",
+                    trace_stack.get_current_source_file()
+                );
+                siv.call_on_name("footer_view", |fview: &mut views::FooterView| {
+                    views::set_footer_view(fview, &footer);
+                });
+                Ok(())
+            }
+            Event::TraceCommandModified => {
+                Controller::rerun_tracer(siv);
                 Ok(())
             }
             Event::SearchResults {
@@ -321,7 +531,57 @@ impl Controller {
                 views::update_search_view(siv, &view_name, results);
                 Ok(())
             }
-            Event::SelectedFunction(_) => {
+            Event::IndexingCalls(percent) => {
+                views::set_progress_view(siv, &format!("Decoded {}% of functions", percent));
+                Ok(())
+            }
+            Event::CallsIndexed {
+                object_path,
+                callers,
+            } => {
+                // Progress view
+                siv.pop_layer();
+                let controller = siv
+                    .user_data::<Controller>()
+                    .expect("Bug: Controller does not exist");
+                controller.is_indexing_calls = false;
+                controller
+                    .get_program_mut(&object_path)
+                    .set_callers_index(callers);
+                if controller.trace_stack.get_current_object_path() == object_path {
+                    Controller::show_callers(siv);
+                }
+                Ok(())
+            }
+            Event::SymbolTreeBuilt(tree) => {
+                // Progress view
+                siv.pop_layer();
+                let controller = siv
+                    .user_data::<Controller>()
+                    .expect("Bug: Controller does not exist");
+                controller.is_building_symbol_tree = false;
+                controller.symbol_tree = Some(Rc::new(tree));
+                Controller::show_symbol_tree(siv);
+                Ok(())
+            }
+            Event::LineTablesIndexed {
+                object_path,
+                line_index,
+            } => {
+                let controller = siv
+                    .user_data::<Controller>()
+                    .expect("Bug: Controller does not exist");
+                controller
+                    .get_program_mut(&object_path)
+                    .set_line_index(line_index);
+                if object_path == controller.program.file_path {
+                    controller
+                        .searcher
+                        .set_symbols(controller.program.symbols_generator());
+                }
+                Ok(())
+            }
+            Event::SelectedFunction(_) | Event::LoadProgress(_) | Event::ProgramLoaded(_) => {
                 panic!("Unexpected event");
             }
         };
@@ -335,35 +595,33 @@ impl Controller {
 
     fn setup_function(
         program: &Program,
+        sources: &Sources,
         function: FunctionName,
         sview: &mut views::SourceView,
         fview: &mut views::FooterView,
     ) -> Result<FrameInfo, Error> {
         let frame_info = Controller::create_frame_info(program, function)?;
-        Controller::setup_source_view(&frame_info, sview, fview)?;
+        Controller::setup_source_view(&frame_info, sources, sview, fview)?;
         Ok(frame_info)
     }
 
     fn setup_source_view(
         frame_info: &FrameInfo,
+        sources: &Sources,
         sview: &mut views::SourceView,
         fview: &mut views::FooterView,
     ) -> Result<(), Error> {
-        let source_code: Vec<String> = match std::fs::File::open(frame_info.get_source_file()) {
-            Ok(file) => {
-                // FIXME we can cache file contents
-                std::io::BufReader::new(file)
-                    .lines()
-                    .map(|l| l.unwrap())
-                    .collect()
-            }
-            Err(_) => {
-                // TODO show error and confirm user wants to display empty lines
-                // instead
-                let max_line = frame_info.max_line();
-                vec![String::new(); max_line as usize]
-            }
-        };
+        // FIXME we can cache file contents
+        let source_code: Vec<String> =
+            match sources.read_lines(frame_info.get_source_file(), frame_info.get_comp_dir()) {
+                Some(lines) => lines,
+                None => {
+                    // TODO show error and confirm user wants to display empty lines
+                    // instead
+                    let max_line = frame_info.max_line();
+                    vec![String::new(); max_line as usize]
+                }
+            };
         views::set_source_view(
             sview,
             source_code,
@@ -385,85 +643,66 @@ impl Controller {
             source_line
         );
 
-        // TODO
-        let (start_address, code) = program.get_data(function).unwrap();
-        let decoder = program::create_decoder();
-
         let mut line_to_callsites = HashMap::<u32, Vec<CallInstruction>>::new();
         let mut unattached_callsites = Vec::<CallInstruction>::new();
 
-        for (instruction, ip) in
-            program::get_instructions_with_mnemonic(&decoder, start_address, code, Mnemonic::CALL)
-        {
-            let relative_ip = u32::try_from(ip - start_address).unwrap();
-            assert!(instruction.operand_count > 0);
-            let operand = &instruction.operands[0];
-            let call_instruction = match operand.reg {
-                Register::NONE => match operand.mem.base {
-                    Register::NONE => {
-                        let call_address = instruction
-                            .calc_absolute_address(ip, &instruction.operands[0])
-                            .unwrap();
-                        match program.get_function_for_address(call_address) {
-                            Some(function) => {
-                                if program.is_dynamic_symbol_address(call_address) {
-                                    CallInstruction::dynamic_symbol(
-                                        relative_ip,
-                                        instruction.length,
-                                        function,
-                                    )
-                                } else {
-                                    CallInstruction::function(
-                                        relative_ip,
-                                        instruction.length,
-                                        function,
-                                    )
-                                }
-                            }
-                            None => CallInstruction::unknown(relative_ip, instruction.length),
-                        }
-                    }
-                    r => CallInstruction::register(
-                        relative_ip,
-                        instruction.length,
-                        r.get_string().unwrap().to_string(),
-                        Some(operand.mem.disp.displacement),
-                    ),
-                },
-                r => {
-                    // TODO convert register string to bpftrace register
-                    CallInstruction::register(
-                        relative_ip,
-                        instruction.length,
-                        r.get_string().unwrap().to_string(),
-                        None,
-                    )
+        // Decode the main function along with any parts of it that were split
+        // off by the compiler (e.g. `.cold` parts).
+        let function_address = program.get_address(function);
+        let parts: Vec<FunctionName> = iter::once(function)
+            .chain(program.get_function_parts(function))
+            .collect();
+        let mut call_instructions = Vec::<(CallInstruction, u64)>::new();
+        for &part in &parts {
+            // TODO
+            let (start_address, code) = program.get_data(part).unwrap();
+            let mut part_call_instructions = Controller::get_calls(program, start_address, code);
+            part_call_instructions.extend(Controller::get_tail_calls(
+                program,
+                &parts,
+                start_address,
+                code,
+            ));
+            if part != function {
+                log::info!("Function {} has separate part {}", function, part);
+                for (call_instruction, _) in &mut part_call_instructions {
+                    call_instruction.set_part(part, start_address as i64 - function_address as i64);
+                }
+            }
+            call_instructions.extend(part_call_instructions);
+        }
+
+        for (mut call_instruction, ip) in call_instructions {
+            match program.get_inlined_location(ip, source_file) {
+                Some((line, inline_chain)) => {
+                    call_instruction.set_inline_chain(inline_chain);
+                    line_to_callsites
+                        .entry(line)
+                        .or_default()
+                        .push(call_instruction);
+                }
+                None => {
+                    // We don't know which line this corresponds to in the
+                    // source file we are displaying.
+                    let location = program.get_location(ip).unwrap();
+                    log::trace!(
+                        "Not displaying function call {} from {}:{} because it is not in current source file {}",
+                        call_instruction,
+                        location.file.unwrap(),
+                        location.line.unwrap(),
+                        source_file
+                    );
+                    unattached_callsites.push(call_instruction);
                 }
-            };
-            let location = program.get_location(ip).unwrap();
-            if location.file.unwrap() == source_file {
-                line_to_callsites
-                    .entry(location.line.unwrap())
-                    .or_default()
-                    .push(call_instruction);
-            } else {
-                // This is an inlined call. We don't know which line it
-                // corresponds to in the source file we are displaying.
-                log::trace!(
-                    "Not displaying function call {} from {}:{} because it is not in current source file {}",
-                    call_instruction,
-                    location.file.unwrap(),
-                    location.line.unwrap(),
-                    source_file
-                );
-                unattached_callsites.push(call_instruction);
             }
         }
 
         log::trace!("{:?}", line_to_callsites);
         let frame_info = FrameInfo::new(
             function,
+            program.file_path.clone(),
             String::from(source_file),
+            program.get_comp_dir(program.get_address(function)),
             source_line,
             line_to_callsites,
             unattached_callsites,
@@ -472,6 +711,489 @@ impl Controller {
         Ok(frame_info)
     }
 
+    /// Decode the call instructions in `code` (starting at `start_address`),
+    /// returning them along with their addresses
+    fn get_calls(
+        program: &Program,
+        start_address: u64,
+        code: &[u8],
+    ) -> Vec<(CallInstruction, u64)> {
+        let mut call_instructions = Vec::<(CallInstruction, u64)>::new();
+        for call in program.arch().calls(start_address, code) {
+            let ip = call.address;
+            let relative_ip = u32::try_from(ip - start_address).unwrap();
+            let call_instruction = match call.target {
+                BranchTarget::Address(call_address) => {
+                    match program.get_function_for_address(call_address) {
+                        Some(function) => {
+                            if program.is_dynamic_symbol_address(call_address) {
+                                CallInstruction::dynamic_symbol(relative_ip, call.length, function)
+                            } else {
+                                CallInstruction::function(relative_ip, call.length, function)
+                            }
+                        }
+                        None => CallInstruction::unknown(relative_ip, call.length),
+                    }
+                }
+                BranchTarget::Memory {
+                    register,
+                    displacement,
+                    ..
+                } => CallInstruction::register(
+                    relative_ip,
+                    call.length,
+                    register,
+                    Some(displacement),
+                ),
+                BranchTarget::Register(register) => {
+                    CallInstruction::register(relative_ip, call.length, register, None)
+                }
+                BranchTarget::Unknown => CallInstruction::unknown(relative_ip, call.length),
+            };
+            call_instructions.push((call_instruction, ip));
+        }
+        call_instructions
+    }
+
+    /// Find jumps to other functions at the end of a function (i.e. tail
+    /// calls). Only direct jumps to the start of a known function or to a PLT
+    /// entry, and indirect jumps through the GOT, are considered - other
+    /// indirect jumps are indistinguishable from e.g. jump tables. `parts` are
+    /// the function and its separate parts, jumps between which are not tail
+    /// calls.
+    fn get_tail_calls(
+        program: &Program,
+        parts: &[FunctionName],
+        start_address: u64,
+        code: &[u8],
+    ) -> Vec<(CallInstruction, u64)> {
+        let mut tail_calls = Vec::new();
+        for jump in program.arch().jumps(start_address, code) {
+            let target = match jump.target {
+                BranchTarget::Address(jump_address) => {
+                    match program.get_function_for_address(jump_address) {
+                        Some(f) if parts.contains(&f) => None,
+                        Some(f) if program.is_dynamic_symbol_address(jump_address) => {
+                            Some(InstructionType::DynamicSymbol(f))
+                        }
+                        Some(f) => Some(InstructionType::Function(f)),
+                        None => None,
+                    }
+                }
+                BranchTarget::Memory {
+                    address: Some(got_address),
+                    ..
+                } => program
+                    .get_function_for_got_entry(got_address)
+                    .map(InstructionType::DynamicSymbol),
+                _ => None,
+            };
+            if let Some(target) = target {
+                let relative_ip = u32::try_from(jump.address - start_address).unwrap();
+                tail_calls.push((
+                    CallInstruction::tail_call(relative_ip, jump.length, target),
+                    jump.address,
+                ));
+            }
+        }
+        tail_calls
+    }
+
+    /// Returns the program or shared library at `path`, which must be loaded
+    fn get_program(&self, path: &str) -> &Program {
+        if path == self.program.file_path {
+            &self.program
+        } else {
+            self.libraries
+                .get(path)
+                .expect("Bug: shared library has not been loaded")
+        }
+    }
+
+    fn get_program_mut(&mut self, path: &str) -> &mut Program {
+        if path == self.program.file_path {
+            &mut self.program
+        } else {
+            self.libraries
+                .get_mut(path)
+                .expect("Bug: shared library has not been loaded")
+        }
+    }
+
+    /// Show the functions of the program grouped by namespace and class, to
+    /// select one to push onto the trace stack. The tree must have been
+    /// built.
+    fn show_symbol_tree(siv: &mut Cursive) {
+        let tree = siv
+            .user_data::<Controller>()
+            .expect("Bug: Controller does not exist")
+            .symbol_tree
+            .clone()
+            .expect("Bug: symbol tree has not been built");
+        let tree_view = views::new_symbol_tree_view(
+            "Select the function to enter",
+            tree,
+            move |siv: &mut Cursive, symbol: &SymbolInfo| {
+                let program_path = siv
+                    .user_data::<Controller>()
+                    .expect("Bug: Controller does not exist")
+                    .program
+                    .file_path
+                    .clone();
+                Controller::push_function(siv, &program_path, symbol);
+            },
+        );
+        siv.add_layer(tree_view);
+    }
+
+    /// Show the callers of the current function, to select one to insert
+    /// below it in the trace stack. The callers index must have been built.
+    fn show_callers(siv: &mut Cursive) {
+        let controller = siv
+            .user_data::<Controller>()
+            .expect("Bug: Controller does not exist");
+        let trace_stack = &controller.trace_stack;
+        let function = trace_stack.get_current_function();
+        let object_path = trace_stack.get_current_object_path();
+        let program = controller.get_program(&object_path);
+        let callers: Vec<Caller> = program
+            .get_callers(function)
+            .expect("Bug: callers index has not been built")
+            .into_iter()
+            .map(|(caller, ip)| Caller {
+                function: caller,
+                location: match program.get_location(ip) {
+                    Some(l) => format!("{}:{}", l.file.unwrap(), l.line.unwrap()),
+                    None => format!("{:#x}", ip),
+                },
+            })
+            .collect();
+        if callers.is_empty() {
+            siv.add_layer(views::new_dialog(&format!(
+                "No direct calls to {} found in {}",
+                function, object_path
+            )));
+            return;
+        }
+
+        let search_view = views::new_simple_search_view(
+            "Select the caller to insert below the current function",
+            callers,
+            move |siv: &mut Cursive, caller: &Caller| {
+                let controller = siv
+                    .user_data::<Controller>()
+                    .expect("Bug: Controller does not exist");
+                let program = controller.get_program(&object_path);
+                match Controller::create_frame_info(program, caller.function) {
+                    Ok(frame_info) => controller.trace_stack.insert_below_top(frame_info),
+                    Err(e) => siv.add_layer(views::new_dialog(&format!(
+                        "Error setting up function {}: {}",
+                        caller.function, e
+                    ))),
+                }
+            },
+        );
+        siv.add_layer(search_view);
+    }
+
+    /// Find (and load, if necessary) the shared library containing the
+    /// dynamically linked `function` called from the object at
+    /// `program_path`. Returns the library's path along with the function's
+    /// name within it.
+    fn load_library(
+        &mut self,
+        program_path: &str,
+        function: FunctionName,
+    ) -> Result<(String, FunctionName), Error> {
+        // Strip symbol version, if any
+        let name = function.name.split('@').next().unwrap();
+        let candidates: Vec<String> = match self.trace_stack.get_target() {
+            // Use the libraries that are actually loaded in the process
+            TraceTarget::Pid(pid) => crate::process::mapped_files(*pid)?
+                .into_iter()
+                .filter(|path| path != program_path)
+                .collect(),
+            _ => linker::dependencies(program_path),
+        };
+        let library_path = linker::find_definition(&candidates, name)
+            .ok_or_else(|| format!("Failed to find a shared library defining {}", name))?;
+        log::info!("Found {} in shared library {}", name, library_path);
+        if library_path != self.program.file_path && !self.libraries.contains_key(&library_path) {
+            let debug_dirs = self.program.get_debug_dirs().to_vec();
+            let library = Program::new(library_path.clone(), debug_dirs)?;
+            self.libraries.insert(library_path.clone(), library);
+        }
+        let library_function = self
+            .get_program(&library_path)
+            .find_function(name)
+            .ok_or_else(|| {
+                format!(
+                    "Failed to find symbol information for {} in {}",
+                    name, library_path
+                )
+            })?;
+        Ok((library_path, library_function))
+    }
+
+    /// Map indirect call targets traced in `TraceMode::IndirectTargets` to
+    /// functions in the current frame's program, ranked by number of calls.
+    fn rank_indirect_targets(
+        &self,
+        targets: &[(i64, i64, u64)],
+    ) -> Vec<(String, Option<SymbolInfo>)> {
+        let program = self.get_program(&self.trace_stack.get_current_object_path());
+        let function_address = program.get_address(self.trace_stack.get_current_function());
+        let mut address_to_count = HashMap::<u64, u64>::new();
+        for &(function_offset, offset, count) in targets {
+            let address = function_address
+                .wrapping_add(function_offset as u64)
+                .wrapping_add(offset as u64);
+            *address_to_count.entry(address).or_default() += count;
+        }
+        let mut counts: Vec<(u64, u64)> = address_to_count.into_iter().collect();
+        counts.sort_by(|(a1, c1), (a2, c2)| c2.cmp(c1).then(a1.cmp(a2)));
+        if counts.is_empty() {
+            return vec![("No calls traced yet...".to_string(), None)];
+        }
+        counts
+            .into_iter()
+            .map(|(address, count)| {
+                match program
+                    .get_function_for_address(address)
+                    .and_then(|function| program.get_symbol(function))
+                {
+                    Some(symbol) => (format!("{} calls: {}", count, symbol), Some(symbol.clone())),
+                    None => (format!("{} calls: (UNKNOWN) {:#x}", count, address), None),
+                }
+            })
+            .collect()
+    }
+
+    /// Push `symbol` (from the object at `program_path`) onto the trace
+    /// stack and display it. Dynamically linked functions are pushed from the
+    /// shared library containing them.
+    fn push_function(siv: &mut Cursive, program_path: &str, symbol: &SymbolInfo) {
+        let controller = siv
+            .user_data::<Controller>()
+            .expect("Bug: Controller does not exist");
+        let (object_path, function) = if controller
+            .get_program(program_path)
+            .is_dynamic_symbol(symbol)
+        {
+            match controller.load_library(program_path, symbol.name) {
+                Ok(library_function) => library_function,
+                Err(e) => {
+                    siv.add_layer(views::new_dialog(&format!(
+                        "Error setting up dynamically linked function {}: {}",
+                        symbol.name, e
+                    )));
+                    return;
+                }
+            }
+        } else {
+            (program_path.to_string(), symbol.name)
+        };
+
+        let mut sview = siv
+            .find_name::<views::SourceView>("source_view")
+            .expect("Bug: source_view does not exist");
+        let mut fview = siv
+            .find_name::<views::FooterView>("footer_view")
+            .expect("Bug: footer_view does not exist");
+        // Reset lifetime of `controller` to avoid overlapping mutable borrows
+        // of `siv`.
+        let controller = siv
+            .user_data::<Controller>()
+            .expect("Bug: Controller does not exist");
+        match Controller::setup_function(
+            controller.get_program(&object_path),
+            &controller.sources,
+            function,
+            &mut sview,
+            &mut fview,
+        ) {
+            Err(e) => siv.add_layer(views::new_dialog(&format!(
+                "Error setting up function {}: {}",
+                function, e
+            ))),
+            Ok(frame_info) => {
+                controller.trace_stack.push(frame_info);
+                if program_path == controller.program.file_path {
+                    let symbols = controller.program.symbols_generator();
+                    controller.history.add_recent(symbol.name, &symbols);
+                }
+            }
+        };
+    }
+
+    /// Show source file `file` with the first line of each function in it
+    /// marked. Selecting a marked line pushes its function onto the stack.
+    fn open_source_file(siv: &mut Cursive, file: &str) {
+        let controller = siv
+            .user_data::<Controller>()
+            .expect("Bug: Controller does not exist");
+        let program = &controller.program;
+        let function_lines = program.function_lines(file);
+        let comp_dir = function_lines
+            .first()
+            .and_then(|(_, function)| program.get_comp_dir(function.address));
+        let max_line = function_lines.last().map_or(0, |(line, _)| *line as usize);
+        let mut source_code = controller
+            .sources
+            .read_lines(file, comp_dir.as_deref())
+            .unwrap_or_default();
+        if source_code.len() < max_line {
+            // Missing or out of date source file
+            source_code.resize(max_line, String::new());
+        }
+        let mut marked_lines: Vec<u32> = function_lines.iter().map(|(line, _)| *line).collect();
+        marked_lines.dedup();
+        let file_view = views::new_file_view(
+            file,
+            source_code,
+            marked_lines,
+            move |siv: &mut Cursive, line: u32| {
+                let controller = siv
+                    .user_data::<Controller>()
+                    .expect("Bug: Controller does not exist");
+                let program = &controller.program;
+                let program_path = program.file_path.clone();
+                let mut symbols: Vec<SymbolInfo> = function_lines
+                    .iter()
+                    .filter(|(l, _)| *l == line)
+                    .filter_map(|(_, function)| program.get_symbol(*function).cloned())
+                    .collect();
+                match symbols.len() {
+                    0 => siv.add_layer(views::new_dialog(&format!(
+                        "No function starts on line {}, select a line marked with ▶",
+                        line
+                    ))),
+                    1 => {
+                        siv.pop_layer();
+                        Controller::push_function(siv, &program_path, &symbols.remove(0));
+                    }
+                    _ => {
+                        siv.pop_layer();
+                        let search_view = views::new_simple_search_view(
+                            "Select the function to enter",
+                            symbols,
+                            move |siv: &mut Cursive, symbol: &SymbolInfo| {
+                                Controller::push_function(siv, &program_path, symbol)
+                            },
+                        );
+                        siv.add_layer(search_view);
+                    }
+                }
+            },
+        );
+        siv.add_layer(file_view);
+    }
+
+    /// Push a single frame tracing all functions in the program matching
+    /// `pattern` (see `Program::find_functions_matching`). Functions from
+    /// other source files than the first match are not included, since their
+    /// calls can't be matched up by line.
+    fn push_aggregate_function(siv: &mut Cursive, pattern: &str) {
+        let controller = siv
+            .user_data::<Controller>()
+            .expect("Bug: Controller does not exist");
+        let program = &controller.program;
+        let functions = program.find_functions_matching(pattern);
+        let (&function, others) = match functions.split_first() {
+            Some(split) => split,
+            None => {
+                let message = format!(
+                    "No functions matching {} found in {}",
+                    pattern, program.file_path
+                );
+                siv.add_layer(views::new_dialog(&message));
+                return;
+            }
+        };
+        let mut frame_info = match Controller::create_frame_info(program, function) {
+            Ok(frame_info) => frame_info,
+            Err(e) => {
+                siv.add_layer(views::new_dialog(&format!(
+                    "Error setting up function {}: {}",
+                    function, e
+                )));
+                return;
+            }
+        };
+        // All functions are probed, but calls are only matched up across
+        // those whose calls line up with the first's
+        let mut n_unmatched = 0;
+        for &other in others {
+            match Controller::create_frame_info(program, other) {
+                Ok(other_info) => {
+                    if !frame_info.aggregate(other_info) {
+                        log::info!("Not aggregating calls of {}: calls don't line up", other);
+                        n_unmatched += 1;
+                    }
+                }
+                Err(e) => {
+                    log::warn!("Not aggregating calls of {}: {}", other, e);
+                    frame_info.aggregate_function(other);
+                    n_unmatched += 1;
+                }
+            }
+        }
+        log::info!(
+            "Aggregating {} functions matching {}, calls of {} are not matched up",
+            functions.len(),
+            pattern,
+            n_unmatched
+        );
+
+        let mut sview = siv
+            .find_name::<views::SourceView>("source_view")
+            .expect("Bug: source_view does not exist");
+        let mut fview = siv
+            .find_name::<views::FooterView>("footer_view")
+            .expect("Bug: footer_view does not exist");
+        // Reset lifetime of `controller` to avoid overlapping mutable borrows
+        // of `siv`.
+        let controller = siv
+            .user_data::<Controller>()
+            .expect("Bug: Controller does not exist");
+        if let Err(e) =
+            Controller::setup_source_view(&frame_info, &controller.sources, &mut sview, &mut fview)
+        {
+            siv.add_layer(views::new_dialog(&format!(
+                "Error setting up function {}: {}",
+                function, e
+            )));
+            return;
+        }
+        let source_file = frame_info.get_source_file().to_string();
+        controller.trace_stack.push(frame_info);
+        if n_unmatched > 0 {
+            siv.add_layer(views::new_dialog(&format!(
+                "Tracing {} functions matching {}. Calls of {} of them are matched up line by line. The other {} (defined outside {}, or whose calls don't line up with those of {}) are excluded from per-line results, and only included in the function's latency and frequency.",
+                functions.len(),
+                pattern,
+                functions.len() - n_unmatched,
+                n_unmatched,
+                source_file,
+                function
+            )));
+        }
+    }
+
+    fn rerun_tracer(siv: &mut Cursive) {
+        let controller = siv
+            .user_data::<Controller>()
+            .expect("Bug: Controller does not exist");
+        controller.tracer.rerun_tracer();
+        if let TraceTarget::Command(_) = controller.trace_stack.get_target() {
+            // Clear any previous message about the program having exited
+            let source_file = controller.trace_stack.get_current_source_file();
+            siv.call_on_name("footer_view", |fview: &mut views::FooterView| {
+                views::set_footer_view(fview, &source_file);
+            });
+        }
+    }
+
     fn set_line_state(
         sview: &mut views::SourceView,
         line: u32,
@@ -713,10 +1435,14 @@ impl Controller {
             let controller = siv
                 .user_data::<Controller>()
                 .expect("Bug: Controller does not exist");
-            let initial_results = vec![("Type to search".to_string(), None)];
+            let initial_results = Controller::default_search_results(
+                &controller.history,
+                &controller.program.symbols_generator(),
+                "Type to search, or enter a source location (file:line or directory)",
+            );
             controller
                 .searcher
-                .setup_search(initial_results.clone(), Vec::new());
+                .setup_search(initial_results.clone(), Vec::new(), true);
             let search_view = views::new_search_view(
                 "Select the function to enter",
                 initial_results,
@@ -727,51 +1453,113 @@ impl Controller {
                     controller.searcher.search(view_name, search, n_results);
                 },
                 move |siv: &mut Cursive, symbol: &SymbolInfo| {
-                    let controller = siv
-                        .user_data::<Controller>()
-                        .expect("Bug: Controller does not exist");
                     // TODO cancel any pending searches
-                    if controller.program.is_dynamic_symbol(symbol) {
-                        // TODO show error for dyn fn
-                    } else {
-                        let mut sview = siv
-                            .find_name::<views::SourceView>("source_view")
-                            .expect("Bug: source_view does not exist");
-                        let mut fview = siv
-                            .find_name::<views::FooterView>("footer_view")
-                            .expect("Bug: footer_view does not exist");
-                        // Reset lifetime of `controller` to avoid overlapping
-                        // mutable borrows of `siv`.
-                        let controller = siv
-                            .user_data::<Controller>()
-                            .expect("Bug: Controller does not exist");
-                        match Controller::setup_function(
-                            &controller.program,
-                            symbol.name,
-                            &mut *sview,
-                            &mut *fview,
-                        ) {
-                            Err(e) => siv.add_layer(views::new_dialog(&format!(
-                                "Error setting up function {}: {}",
-                                symbol.name, e
-                            ))),
-                            Ok(frame_info) => {
-                                controller.trace_stack.push(frame_info);
-                            }
-                        };
-                    }
+                    let program_path = siv
+                        .user_data::<Controller>()
+                        .expect("Bug: Controller does not exist")
+                        .program
+                        .file_path
+                        .clone();
+                    Controller::push_function(siv, &program_path, symbol);
                 },
             );
             siv.add_layer(search_view);
         });
 
-        KeyHandler::add_global_callback(siv, 'r', |siv| {
-            siv.user_data::<Controller>()
+        KeyHandler::add_global_callback(siv, 'p', |siv| {
+            let controller = siv
+                .user_data::<Controller>()
+                .expect("Bug: Controller does not exist");
+            let function = controller.trace_stack.get_current_function();
+            let message = if controller.trace_stack.get_current_object_path()
+                != controller.program.file_path
+            {
+                format!(
+                    "Only functions in {} can be pinned",
+                    controller.program.file_path
+                )
+            } else if controller
+                .history
+                .toggle_favourite(function, &controller.program.symbols_generator())
+            {
+                format!("Pinned {}", function)
+            } else {
+                format!("Unpinned {}", function)
+            };
+            siv.add_layer(views::new_dialog(&message));
+        });
+
+        KeyHandler::add_global_callback(siv, 'n', |siv| {
+            let controller = siv
+                .user_data::<Controller>()
+                .expect("Bug: Controller does not exist");
+            if controller.symbol_tree.is_some() {
+                return Controller::show_symbol_tree(siv);
+            }
+            if controller.is_building_symbol_tree {
+                return;
+            }
+            // Splitting all function names into scopes can take a while for
+            // large programs
+            controller.is_building_symbol_tree = true;
+            let symbols = controller.program.symbols_generator();
+            let title = format!("Loading functions of {}", controller.program.file_path);
+            let tx = controller.tx.clone();
+            siv.add_layer(views::new_progress_view(
+                &title,
+                "Grouping functions by namespace and class",
+            ));
+            thread::spawn(move || {
+                // Sending fails if we are exiting, so errors are ignored
+                let tree = SymbolTree::new(symbols.into_iter());
+                let _ = tx.send(Event::SymbolTreeBuilt(tree));
+            });
+        });
+
+        KeyHandler::add_global_callback(siv, 'o', |siv| {
+            let source_files = match siv
+                .user_data::<Controller>()
                 .expect("Bug: Controller does not exist")
-                .tracer
-                .rerun_tracer();
+                .program
+                .source_files()
+            {
+                Some(source_files) => source_files,
+                None => {
+                    siv.add_layer(views::new_dialog(
+                        "Source files can be opened once line tables have been indexed, try again shortly",
+                    ));
+                    return;
+                }
+            };
+            let search_view = views::new_simple_search_view(
+                "Select the source file to open",
+                source_files,
+                |siv: &mut Cursive, file: &String| Controller::open_source_file(siv, file),
+            );
+            siv.add_layer(search_view);
         });
 
+        KeyHandler::add_global_callback(siv, 'a', |siv| {
+            if siv
+                .find_name::<cursive::views::EditView>("pattern_view")
+                .is_some()
+            {
+                // View is already open, make it no-op
+                return;
+            }
+            siv.add_layer(views::new_edit_view(
+                "Enter pattern of functions to enter as one frame (* matches anything)",
+                "pattern_view",
+                None,
+                |siv, pattern| {
+                    siv.pop_layer();
+                    Controller::push_aggregate_function(siv, pattern);
+                },
+            ));
+        });
+
+        KeyHandler::add_global_callback(siv, 'r', Controller::rerun_tracer);
+
         KeyHandler::add_global_callback(
             siv,
             cursive::event::Event::Key(cursive::event::Key::Enter),
@@ -786,6 +1574,8 @@ impl Controller {
                     .user_data::<Controller>()
                     .expect("Bug: Controller does not exist");
                 let trace_stack = &controller.trace_stack;
+                let object_path = trace_stack.get_current_object_path();
+                let program = controller.get_program(&object_path);
                 let callsites = trace_stack.get_callsites(line);
                 if callsites.is_empty() {
                     let function = trace_stack.get_current_function();
@@ -797,64 +1587,42 @@ impl Controller {
                 }
 
                 let num_callsites = callsites.len();
+                // Calls through `[register+displacement]` may be virtual calls,
+                // find functions at that offset in any vtable. Memory relative
+                // to the instruction pointer is a fixed address instead.
+                let ip_register = program.arch().bpftrace_ip_register();
+                let mut vtable_candidates: Vec<SymbolInfo> = callsites
+                    .iter()
+                    .filter_map(|ci| match &ci.instruction {
+                        InstructionType::Register(register, Some(displacement))
+                            if register != ip_register && *displacement >= 0 =>
+                        {
+                            Some(*displacement as u64)
+                        }
+                        _ => None,
+                    })
+                    .flat_map(|displacement| program.get_vtable_candidates(displacement))
+                    .filter_map(|function| program.get_symbol(function))
+                    .cloned()
+                    .collect();
+                vtable_candidates.sort_by_key(|si| (si.name.name, si.name.address));
+                vtable_candidates.dedup_by_key(|si| si.name);
                 let direct_calls: Vec<SymbolInfo> = callsites
                     .into_iter()
-                    .filter_map(|ci| match ci.instruction {
-                        InstructionType::Unknown => None,
-                        InstructionType::Manual => None,
-                        InstructionType::Register(_, _) => None,
-                        InstructionType::DynamicSymbol(function) => {
-                            controller.program.get_symbol(function).or_else(|| {
-                                log::warn!("Could not get symbol information for {}", function);
-                                None
-                            })
-                        }
-                        InstructionType::Function(function) => {
-                            controller.program.get_symbol(function).or_else(|| {
-                                log::warn!("Could not get symbol information for {}", function);
-                                None
-                            })
-                        }
+                    .filter_map(|ci| {
+                        let function = ci.instruction.get_function()?;
+                        program.get_symbol(function).or_else(|| {
+                            log::warn!("Could not get symbol information for {}", function);
+                            None
+                        })
                     })
                     .map(|si| si.clone())
                     .collect();
                 let num_indirect_calls = num_callsites - direct_calls.len();
 
                 let submit_fn = move |siv: &mut Cursive, symbol: &SymbolInfo| {
-                    let controller = siv
-                        .user_data::<Controller>()
-                        .expect("Bug: Controller does not exist");
                     // TODO cancel any pending searches
-                    if controller.program.is_dynamic_symbol(symbol) {
-                        // TODO show error for dyn fn
-                    } else {
-                        let mut sview = siv
-                            .find_name::<views::SourceView>("source_view")
-                            .expect("Bug: source_view does not exist");
-                        let mut fview = siv
-                            .find_name::<views::FooterView>("footer_view")
-                            .expect("Bug: footer_view does not exist");
-                        // Reset lifetime of `controller` to avoid overlapping
-                        // mutable borrows of `siv`.
-                        let controller = siv
-                            .user_data::<Controller>()
-                            .expect("Bug: Controller does not exist");
-                        match Controller::setup_function(
-                            &controller.program,
-                            symbol.name,
-                            &mut *sview,
-                            &mut *fview,
-                        ) {
-                            Err(e) => siv.add_layer(views::new_dialog(&format!(
-                                "Error setting up function {}: {}",
-                                symbol.name, e
-                            ))),
-                            Ok(frame_info) => {
-                                controller.trace_stack.push(frame_info);
-                            }
-                        };
-                    }
-                    // TODO show error for dyn fn
+                    Controller::push_function(siv, &object_path, symbol);
                 };
 
                 if num_callsites > 1 || num_indirect_calls > 0 {
@@ -871,9 +1639,25 @@ impl Controller {
                         };
                         initial_results
                             .insert(0, (format!("{} (type to search)", call_string), None));
-                        controller
-                            .searcher
-                            .setup_search(initial_results.clone(), direct_calls);
+                        if !vtable_candidates.is_empty() {
+                            initial_results.push((
+                                format!(
+                                    "Possible virtual call targets ({}):",
+                                    vtable_candidates.len()
+                                ),
+                                None,
+                            ));
+                            initial_results.extend(search::rank_fn(
+                                vtable_candidates.iter(),
+                                "",
+                                usize::MAX,
+                            ));
+                        }
+                        controller.searcher.setup_search(
+                            initial_results.clone(),
+                            direct_calls,
+                            false,
+                        );
                         views::new_search_view(
                             title,
                             initial_results,
@@ -906,10 +1690,11 @@ impl Controller {
                         .pop_layer()
                         .expect("Pop unexpectedly empty despite len > 1");
 
-                    // Check if this is histogram or breakdown view - we need to
-                    // reset mode if so.
+                    // Check if this is histogram, breakdown or indirect
+                    // targets view - we need to reset mode if so.
                     if views::is_text_dialog_view(&view, "histogram_view")
                         || views::is_text_dialog_view(&view, "breakdown_view")
+                        || views::is_search_view(&*view, Controller::INDIRECT_TARGETS_TITLE)
                     {
                         siv.user_data::<Controller>()
                             .expect("Bug: Controller does not exist")
@@ -930,14 +1715,101 @@ impl Controller {
                         let mut fview = siv
                             .find_name::<views::FooterView>("footer_view")
                             .expect("Bug: footer_view does not exist");
-                        Controller::setup_source_view(&frame_info, &mut *sview, &mut *fview)
-                            .unwrap();
+                        let controller = siv
+                            .user_data::<Controller>()
+                            .expect("Bug: Controller does not exist");
+                        Controller::setup_source_view(
+                            &frame_info,
+                            &controller.sources,
+                            &mut sview,
+                            &mut fview,
+                        )
+                        .unwrap();
                     }
                     None => siv.add_layer(views::new_quit_dialog("Are you sure you want to quit?")),
                 }
             },
         );
 
+        KeyHandler::add_global_callback(siv, 'c', |siv| {
+            let controller = siv
+                .user_data::<Controller>()
+                .expect("Bug: Controller does not exist");
+            if controller.is_indexing_calls {
+                return;
+            }
+            let object_path = controller.trace_stack.get_current_object_path();
+            let indexer = match controller.get_program_mut(&object_path).callers_indexer() {
+                Some(indexer) => indexer,
+                None => return Controller::show_callers(siv),
+            };
+            // Decoding all functions can take a while for large programs
+            controller.is_indexing_calls = true;
+            let tx = controller.tx.clone();
+            siv.add_layer(views::new_progress_view(
+                &format!("Indexing calls in {}", object_path),
+                "Decoding functions",
+            ));
+            thread::spawn(move || {
+                // Sending fails if we are exiting, so errors are ignored
+                let callers = indexer.run(|percent| {
+                    let _ = tx.send(Event::IndexingCalls(percent));
+                });
+                let _ = tx.send(Event::CallsIndexed {
+                    object_path,
+                    callers,
+                });
+            });
+        });
+
+        KeyHandler::add_global_callback(siv, 'i', |siv| {
+            let sview = siv
+                .find_name::<views::SourceView>("source_view")
+                .expect("Bug: source_view does not exist");
+            let line = sview.row().unwrap() as u32 + 1;
+            let controller = siv
+                .user_data::<Controller>()
+                .expect("Bug: Controller does not exist");
+            let trace_stack = &controller.trace_stack;
+            let has_indirect_calls = trace_stack
+                .get_callsites(line)
+                .iter()
+                .any(|ci| matches!(ci.instruction, InstructionType::Register(_, _)));
+            if !has_indirect_calls {
+                let function = trace_stack.get_current_function();
+                siv.add_layer(views::new_dialog(&format!(
+                    "No indirect calls found in {} on line {}",
+                    function, line
+                )));
+                return;
+            }
+
+            trace_stack.set_mode(TraceMode::IndirectTargets { line });
+            let object_path = trace_stack.get_current_object_path();
+            let initial_results = vec![("Gathering call targets...".to_string(), None)];
+            controller
+                .searcher
+                .setup_search(initial_results.clone(), Vec::new(), false);
+            let search_view = views::new_search_view(
+                Controller::INDIRECT_TARGETS_TITLE,
+                initial_results,
+                move |siv: &mut Cursive, view_name: &str, search: &str, n_results: usize| {
+                    let controller = siv
+                        .user_data::<Controller>()
+                        .expect("Bug: Controller does not exist");
+                    controller.searcher.search(view_name, search, n_results);
+                },
+                move |siv: &mut Cursive, symbol: &SymbolInfo| {
+                    siv.user_data::<Controller>()
+                        .expect("Bug: Controller does not exist")
+                        .trace_stack
+                        .set_mode(TraceMode::Line);
+                    Controller::push_function(siv, &object_path, symbol);
+                },
+            );
+            siv.add_layer(search_view);
+        });
+
         KeyHandler::add_global_callback(siv, 'h', |siv| {
             if let Some(_) = siv.find_name::<views::TextDialogView>("histogram_view") {
                 // View is already open, make it no-op
@@ -998,7 +1870,7 @@ impl Controller {
             let initial_results = vec![("Type to search".to_string(), None)];
             controller
                 .searcher
-                .setup_search(initial_results.clone(), Vec::new());
+                .setup_search(initial_results.clone(), Vec::new(), false);
             let search_view = views::new_search_view(
                 "Select the functions to trace",
                 initial_results,
@@ -1017,7 +1889,7 @@ impl Controller {
                         // TODO show error for dyn fn
                     } else {
                         // TODO need way better layout, way to exit, remove fns etc
-                        if symbol.name.0 == "main" {
+                        if symbol.name.name == "main" {
                             controller.trace_stack.set_mode(TraceMode::Breakdown);
                             let current_function = controller.trace_stack.get_current_function();
                             siv.add_layer(views::new_text_dialog_view(
@@ -1048,7 +1920,7 @@ impl Controller {
             let initial_results = vec![("Type to search".to_string(), None)];
             controller
                 .searcher
-                .setup_search(initial_results.clone(), Vec::new());
+                .setup_search(initial_results.clone(), Vec::new(), false);
             let search_view = views::new_search_view(
                 "Select a function to get its mangled name",
                 initial_results,
@@ -1133,6 +2005,26 @@ impl KeyHandler {
     }
 }
 
+/// A direct call to a function, see `Program::get_callers`
+#[derive(Clone)]
+struct Caller {
+    function: FunctionName,
+    /// Source location of the call
+    location: String,
+}
+
+impl fmt::Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.function, self.location)
+    }
+}
+
+impl search::Label for Caller {
+    fn label(&self) -> Cow<'_, str> {
+        Cow::Owned(self.to_string())
+    }
+}
+
 impl search::Label for CallInstruction {
     fn label(&self) -> Cow<str> {
         Cow::Owned(self.to_string())
@@ -1143,3 +2035,31 @@ impl search::Label for program::SymbolInfo {
         Cow::Borrowed(self.as_ref())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_frame_info_parts() {
+        crate::cache::use_test_cache_home();
+        let path = format!("{}/testdata/cold", env!("CARGO_MANIFEST_DIR"));
+        let program = Program::new(path.clone(), Vec::new()).unwrap();
+        let check = program.find_function("check").unwrap();
+        let frame = Controller::create_frame_info(&program, check).unwrap();
+        // Both calls to `report` are in the `.cold` part
+        assert!(frame.called_lines().contains(&7));
+
+        let (tx, _rx) = mpsc::channel();
+        let stack = TraceStack::new(path.clone(), "ip", TraceTarget::All, frame, tx);
+        let mut callsites = stack.get_callsites(7);
+        assert_eq!(callsites.len(), 2);
+        stack.add_callsite(7, callsites.remove(0));
+        let (args, _) = stack.get_bpftrace_args();
+        let expr = args.last().unwrap();
+        // The call is probed relative to the part's symbol, before and after
+        // the call instruction
+        assert!(expr.contains(&format!("uprobe:{}:check.cold ", path)));
+        assert!(expr.contains(&format!("uprobe:{}:check.cold+5 ", path)));
+    }
+}
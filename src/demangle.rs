@@ -0,0 +1,126 @@
+/// Converts mangled symbol names of a particular language into human readable
+/// names
+pub trait Demangler {
+    /// Returns None if `name` is not a valid mangled name for this demangler
+    fn demangle(&self, name: &str) -> Option<String>;
+}
+
+/// C++ (Itanium ABI) names, e.g. `_ZN3foo3barEi` -> `foo::bar(int)`
+pub struct CppDemangler;
+
+impl Demangler for CppDemangler {
+    fn demangle(&self, name: &str) -> Option<String> {
+        cplus_demangle::demangle(name).ok()
+    }
+}
+
+/// Rust legacy (`_ZN...17h<hash>E`) and v0 (`_R...`) names. The hash is
+/// omitted from demangled names.
+pub struct RustDemangler;
+
+impl Demangler for RustDemangler {
+    fn demangle(&self, name: &str) -> Option<String> {
+        rustc_demangle::try_demangle(name)
+            .ok()
+            .map(|demangled| format!("{:#}", demangled))
+    }
+}
+
+/// D names, e.g. `_D3foo3barFiZv` -> `foo.bar`. Only the qualified name is
+/// demangled, without parameter types.
+pub struct DDemangler;
+
+impl Demangler for DDemangler {
+    fn demangle(&self, name: &str) -> Option<String> {
+        let mut rest = name.strip_prefix("_D")?;
+        let mut parts = Vec::new();
+        loop {
+            let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+            if digits == 0 {
+                break;
+            }
+            let length: usize = rest[..digits].parse().ok()?;
+            let part = rest.get(digits..digits + length)?;
+            parts.push(part);
+            rest = &rest[digits + length..];
+        }
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join("."))
+        }
+    }
+}
+
+/// Picks the demangler for a symbol, based on the language of its
+/// compilation unit (`DW_AT_language`) if known, otherwise based on the
+/// symbol's prefix.
+pub fn get_demangler(name: &str, language: Option<gimli::DwLang>) -> &'static dyn Demangler {
+    match language {
+        Some(gimli::DW_LANG_Rust) => &RustDemangler,
+        Some(gimli::DW_LANG_D) => &DDemangler,
+        Some(_) => &CppDemangler,
+        None if name.starts_with("_R") || is_rust_legacy_name(name) => &RustDemangler,
+        None if name.starts_with("_D") => &DDemangler,
+        None => &CppDemangler,
+    }
+}
+
+/// Demangle `name` (see `get_demangler`), returning None if it isn't a valid
+/// mangled name
+pub fn demangle(name: &str, language: Option<gimli::DwLang>) -> Option<String> {
+    match get_demangler(name, language).demangle(name) {
+        Some(demangled) => Some(demangled),
+        // Compilation units can contain functions from other languages, e.g.
+        // C++ code inlined into Rust
+        None if language.is_some() => get_demangler(name, None).demangle(name),
+        None => None,
+    }
+}
+
+/// Rust legacy names use the C++ prefix, but always end with a path
+/// component of the form `17h<16 hex digits>E`
+fn is_rust_legacy_name(name: &str) -> bool {
+    // LLVM may append suffixes such as `.llvm.1234`
+    let name = name.split('.').next().unwrap();
+    match name
+        .strip_prefix("_ZN")
+        .and_then(|name| name.strip_suffix('E'))
+    {
+        Some(path) if path.len() >= 19 => {
+            let (prefix, hash) = path.split_at(path.len() - 16);
+            prefix.ends_with("17h") && hash.bytes().all(|b| b.is_ascii_hexdigit())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demangle() {
+        assert_eq!(
+            demangle("_ZN3foo3barEi", None),
+            Some("foo::bar(int)".to_string())
+        );
+        assert_eq!(
+            demangle("_ZN5wachy7program7Program3new17h0123456789abcdefE", None),
+            Some("wachy::program::Program::new".to_string())
+        );
+        assert_eq!(
+            demangle("_RNvCs1234_5wachy4main", None),
+            Some("wachy::main".to_string())
+        );
+        assert_eq!(
+            demangle("_D3foo3barFiZv", None),
+            Some("foo.bar".to_string())
+        );
+        assert_eq!(
+            demangle("_ZN3foo3barEi", Some(gimli::DW_LANG_Rust)),
+            Some("foo::bar(int)".to_string())
+        );
+        assert_eq!(demangle("main", None), None);
+    }
+}
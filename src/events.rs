@@ -1,5 +1,8 @@
+use crate::error::Error;
+use crate::line_index::LineIndex;
 use crate::program::FunctionName;
-use crate::program::SymbolInfo;
+use crate::program::{CallersIndex, LoadProgress, Program, SymbolInfo};
+use crate::symbol_tree::SymbolTree;
 use std::collections::HashMap;
 use std::time::Duration;
 
@@ -10,6 +13,12 @@ pub enum Event {
         error_message: String,
     },
     TraceData(TraceInfo),
+    /// The command launched for tracing has exited, so no more trace data will
+    /// be sent for this counter (corresponding to when bpftrace command was
+    /// last updated).
+    TracedProgramExited {
+        counter: u64,
+    },
     TraceCommandModified,
     SearchResults {
         counter: u64,
@@ -17,6 +26,26 @@ pub enum Event {
         results: Vec<(String, Option<SymbolInfo>)>,
     },
     SelectedFunction(FunctionName),
+    /// Progress of loading the program on a background thread
+    LoadProgress(LoadProgress),
+    /// The program has finished loading, sent after all `LoadProgress` events
+    ProgramLoaded(Result<Box<Program>, Error>),
+    /// The line index for the object at `object_path` has been built, sent
+    /// after `ProgramLoaded`
+    LineTablesIndexed {
+        object_path: String,
+        line_index: LineIndex,
+    },
+    /// The tree of the program's functions has been built in the background
+    SymbolTreeBuilt(SymbolTree),
+    /// Percentage of functions decoded while building a callers index in the
+    /// background
+    IndexingCalls(u32),
+    /// The callers index for the object at `object_path` has been built
+    CallsIndexed {
+        object_path: String,
+        callers: CallersIndex,
+    },
 }
 
 /// Format in which trace data is passed back
@@ -39,6 +68,9 @@ pub enum TraceInfoMode {
         /// `TraceStack.breakdown_functions`.
         breakdown_traces: Vec<TraceCumulative>,
     },
+    /// Vector of (call instruction IP relative to function start, target
+    /// address relative to call instruction IP, count)
+    IndirectTargets(Vec<(i64, i64, u64)>),
 }
 
 pub struct TraceCumulative {
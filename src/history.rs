@@ -0,0 +1,306 @@
+use crate::process;
+use crate::program::{FunctionName, SymbolInfo, SymbolsGenerator};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Maximum number of recent functions remembered per program
+const MAX_RECENT: usize = 30;
+
+/// A function, stored by name and address. Addresses change whenever the
+/// program is rebuilt, so a stored function whose address no longer exists is
+/// matched by name only, see `refers_to`.
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredFunction {
+    name: String,
+    address: u64,
+}
+
+impl StoredFunction {
+    fn new(function: FunctionName) -> StoredFunction {
+        StoredFunction {
+            name: function.name.to_string(),
+            address: function.address,
+        }
+    }
+
+    /// Whether this refers to `function`, where `addresses` are the addresses
+    /// of functions with its name in the program
+    fn refers_to(&self, function: FunctionName, addresses: &[u64]) -> bool {
+        self.name == function.name
+            && (self.address == function.address || !addresses.contains(&self.address))
+    }
+}
+
+/// Addresses of the functions named `name` in `symbols`
+fn addresses(symbols: &SymbolsGenerator, name: &str) -> Vec<u64> {
+    symbols
+        .into_iter()
+        .filter(|s| s.name.name == name)
+        .map(|s| s.name.address)
+        .collect()
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Functions {
+    /// Most recent first
+    recent: Vec<StoredFunction>,
+    favourites: Vec<StoredFunction>,
+}
+
+/// Functions of a program that were recently traced or pinned by the user,
+/// stored in `~/.local/state/wachy/` (or under `$XDG_STATE_HOME`) so that
+/// they can be offered before anything is searched for.
+pub struct History {
+    /// None if the state directory can't be determined
+    path: Option<PathBuf>,
+    functions: Functions,
+}
+
+impl History {
+    pub fn new(file_path: &str) -> History {
+        let state_home = match std::env::var_os("XDG_STATE_HOME") {
+            Some(dir) => Some(PathBuf::from(dir)),
+            None => std::env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(".local").join("state")),
+        };
+        // Keyed by path rather than build ID, so that history is kept when
+        // the program is rebuilt. Paths under `/proc/<pid>/` (when tracing a
+        // process) are replaced by the path the process sees, so that history
+        // is kept when it is restarted.
+        let key = process::stable_path(file_path);
+        let path = state_home.map(|dir| {
+            dir.join("wachy")
+                .join(format!("{}.json", key.replace('/', "%")))
+        });
+        History::at(path)
+    }
+
+    fn at(path: Option<PathBuf>) -> History {
+        let mut history = History {
+            path,
+            functions: Functions::default(),
+        };
+        history.functions = history.read();
+        history
+    }
+
+    fn read(&self) -> Functions {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Functions::default(),
+        };
+        let contents = match std::fs::read(path) {
+            Ok(contents) => contents,
+            Err(_) => return Functions::default(),
+        };
+        serde_json::from_slice(&contents).unwrap_or_else(|err| {
+            log::warn!("Ignoring invalid history file {}: {}", path.display(), err);
+            Functions::default()
+        })
+    }
+
+    /// Apply `update` to the latest history (which another instance of wachy
+    /// may have written to) and save it. Failures are only logged.
+    fn update<F: FnOnce(&mut Functions)>(&mut self, update: F) {
+        self.functions = self.read();
+        update(&mut self.functions);
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let dir = path.parent().unwrap();
+        let result = std::fs::create_dir_all(dir)
+            .map_err(|err| err.to_string())
+            .and_then(|_| serde_json::to_vec(&self.functions).map_err(|err| err.to_string()))
+            .and_then(|contents| {
+                let tmp_path = path.with_extension(format!("json.{}", std::process::id()));
+                std::fs::write(&tmp_path, contents)
+                    .and_then(|_| std::fs::rename(&tmp_path, path))
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = result {
+            log::warn!("Failed to write history file {}: {}", path.display(), err);
+        }
+    }
+
+    /// Record that `function` (one of `symbols`) was traced
+    pub fn add_recent(&mut self, function: FunctionName, symbols: &SymbolsGenerator) {
+        let addresses = addresses(symbols, function.name);
+        self.update(|functions| {
+            functions
+                .recent
+                .retain(|f| !f.refers_to(function, &addresses));
+            functions.recent.insert(0, StoredFunction::new(function));
+            functions.recent.truncate(MAX_RECENT);
+        });
+    }
+
+    /// Pin `function` (one of `symbols`), or unpin it if it is already
+    /// pinned. Returns whether it is now pinned.
+    pub fn toggle_favourite(&mut self, function: FunctionName, symbols: &SymbolsGenerator) -> bool {
+        let addresses = addresses(symbols, function.name);
+        let mut is_pinned = false;
+        self.update(|functions| {
+            let n_favourites = functions.favourites.len();
+            functions
+                .favourites
+                .retain(|f| !f.refers_to(function, &addresses));
+            if functions.favourites.len() == n_favourites {
+                functions.favourites.push(StoredFunction::new(function));
+                is_pinned = true;
+            }
+        });
+        is_pinned
+    }
+
+    /// Pinned functions followed by recent ones, for use as search results.
+    /// Functions that no longer exist in `symbols` are skipped.
+    pub fn search_results(&self, symbols: &SymbolsGenerator) -> Vec<(String, Option<SymbolInfo>)> {
+        let stored = || {
+            self.functions
+                .favourites
+                .iter()
+                .map(|f| ("Pinned", f))
+                .chain(self.functions.recent.iter().map(|f| ("Recent", f)))
+        };
+        let mut candidates: HashMap<&str, Vec<&SymbolInfo>> = stored()
+            .map(|(_, f)| (f.name.as_str(), Vec::new()))
+            .collect();
+        for symbol in symbols {
+            if let Some(symbols) = candidates.get_mut(symbol.name.name) {
+                symbols.push(symbol);
+            }
+        }
+
+        let mut results: Vec<(String, Option<SymbolInfo>)> = Vec::new();
+        for (kind, function) in stored() {
+            let symbols = &candidates[function.name.as_str()];
+            let symbol = symbols
+                .iter()
+                .find(|s| s.name.address == function.address)
+                .or_else(|| symbols.first());
+            if let Some(&symbol) = symbol {
+                if results
+                    .iter()
+                    .all(|(_, s)| s.as_ref().unwrap().name != symbol.name)
+                {
+                    results.push((format!("{}: {}", kind, symbol), Some(symbol.clone())));
+                }
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn test_path(name: &str) -> Option<PathBuf> {
+        let path = std::env::temp_dir().join(format!(
+            "wachy_test_history_{}_{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        Some(path)
+    }
+
+    fn generator(symbols: &[SymbolInfo]) -> SymbolsGenerator {
+        SymbolsGenerator::new(symbols, &Arc::new(Vec::new()))
+    }
+
+    fn stored(functions: &[StoredFunction]) -> Vec<(&str, u64)> {
+        functions
+            .iter()
+            .map(|f| (f.name.as_str(), f.address))
+            .collect()
+    }
+
+    #[test]
+    fn test_toggle_favourite() {
+        let path = test_path("favourites");
+        let foo = FunctionName::new("foo", 0x10);
+        let bar = FunctionName::new("bar", 0x20);
+        let symbols = generator(&[SymbolInfo::new("foo", 0x10), SymbolInfo::new("bar", 0x20)]);
+        let mut history = History::at(path.clone());
+        assert!(history.toggle_favourite(foo, &symbols));
+        // Changes made by other instances are kept
+        let mut other_history = History::at(path.clone());
+        assert!(other_history.toggle_favourite(bar, &symbols));
+        assert!(!history.toggle_favourite(foo, &symbols));
+        let history = History::at(path.clone());
+        assert_eq!(stored(&history.functions.favourites), vec![("bar", 0x20)]);
+        std::fs::remove_file(path.unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_same_name() {
+        let path = test_path("same_name");
+        // Local functions in different files
+        let foo_a = FunctionName::new("foo", 0x10);
+        let foo_b = FunctionName::new("foo", 0x20);
+        let symbols = generator(&[SymbolInfo::new("foo", 0x10), SymbolInfo::new("foo", 0x20)]);
+        let mut history = History::at(path.clone());
+        assert!(history.toggle_favourite(foo_a, &symbols));
+        assert!(history.toggle_favourite(foo_b, &symbols));
+        history.add_recent(foo_a, &symbols);
+        history.add_recent(foo_b, &symbols);
+        history.add_recent(foo_a, &symbols);
+        assert_eq!(
+            stored(&history.functions.favourites),
+            vec![("foo", 0x10), ("foo", 0x20)]
+        );
+        assert_eq!(
+            stored(&history.functions.recent),
+            vec![("foo", 0x10), ("foo", 0x20)]
+        );
+
+        // Once the program is rebuilt, functions whose address no longer
+        // exists are matched by name
+        let rebuilt = generator(&[SymbolInfo::new("foo", 0x10), SymbolInfo::new("foo", 0x30)]);
+        let foo_c = FunctionName::new("foo", 0x30);
+        assert!(!history.toggle_favourite(foo_c, &rebuilt));
+        history.add_recent(foo_c, &rebuilt);
+        assert_eq!(stored(&history.functions.favourites), vec![("foo", 0x10)]);
+        assert_eq!(
+            stored(&history.functions.recent),
+            vec![("foo", 0x30), ("foo", 0x10)]
+        );
+        std::fs::remove_file(path.unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_search_results() {
+        let path = test_path("search");
+        let symbols = [
+            SymbolInfo::new("foo", 0x10),
+            SymbolInfo::new("foo", 0x20),
+            SymbolInfo::new("bar", 0x30),
+            SymbolInfo::new("baz", 0x40),
+        ];
+        let symbols = generator(&symbols);
+        let mut history = History::at(path.clone());
+        // The program has since been rebuilt, moving bar
+        history.add_recent(FunctionName::new("bar", 0x38), &symbols);
+        history.add_recent(FunctionName::new("foo", 0x20), &symbols);
+        history.add_recent(FunctionName::new("baz", 0x40), &symbols);
+        history.add_recent(FunctionName::new("removed", 0x50), &symbols);
+        history.add_recent(FunctionName::new("baz", 0x40), &symbols);
+        history.toggle_favourite(FunctionName::new("baz", 0x40), &symbols);
+
+        let results = history.search_results(&symbols);
+        let labels: Vec<_> = results.iter().map(|(label, _)| label.as_str()).collect();
+        // Functions are listed once, and ones that no longer exist are
+        // skipped
+        assert_eq!(labels, vec!["Pinned: baz", "Recent: foo", "Recent: bar"]);
+        let addresses: Vec<_> = results
+            .iter()
+            .map(|(_, symbol)| symbol.as_ref().unwrap().name.address)
+            .collect();
+        assert_eq!(addresses, vec![0x40, 0x20, 0x30]);
+        std::fs::remove_file(path.unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,363 @@
+use crate::program::FunctionName;
+use std::collections::HashMap;
+use std::fmt;
+
+/// File extensions recognized as source files in search queries
+const SOURCE_EXTENSIONS: &[&str] = &[
+    "c", "cc", "cpp", "cxx", "c++", "h", "hh", "hpp", "hxx", "inl", "rs", "go", "d",
+];
+
+/// A search for functions by source location, see `parse_query`
+#[derive(Debug, PartialEq)]
+pub struct LocationQuery<'a> {
+    /// Source file path, directory path, or a suffix of one
+    pub path: &'a str,
+    pub line: Option<u32>,
+}
+
+impl fmt::Display for LocationQuery<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.path, line),
+            None => write!(f, "{}", self.path),
+        }
+    }
+}
+
+/// Parse search input of the form `file:line`, a source file path with a
+/// known extension (e.g. `cache.cc`) or a directory path ending in `/` (e.g.
+/// `src/net/`). Returns None if it isn't a location,
+/// e.g. for function names like `foo::bar` or `operator/`, or for queries
+/// with filters (see `query::parse`). Absolute paths without a line are
+/// treated as regexes, `file:` filters can be used to search them instead.
+pub fn parse_query(search: &str) -> Option<LocationQuery<'_>> {
+    let search = search.trim();
+    if search.is_empty()
+        || search.starts_with('=')
+        || search.starts_with("operator")
+        || search.contains(|c: char| c.is_whitespace() || "()<>,".contains(c))
+    {
+        return None;
+    }
+    if let Some((path, line)) = search.rsplit_once(':') {
+        if !path.is_empty()
+            && !path.contains(':')
+            && !line.is_empty()
+            && line.bytes().all(|b| b.is_ascii_digit())
+        {
+            return Some(LocationQuery {
+                path,
+                line: line.parse().ok(),
+            });
+        }
+    }
+    if search.contains(':') || search.starts_with('/') {
+        return None;
+    }
+    let is_source_file = matches!(
+        search.rsplit_once('.'),
+        Some((_, extension)) if SOURCE_EXTENSIONS.contains(&extension)
+    );
+    // Function names may contain `/` too, e.g. Go's `net/http.Get`
+    if search.ends_with('/') || is_source_file {
+        Some(LocationQuery {
+            path: search,
+            line: None,
+        })
+    } else {
+        None
+    }
+}
+
+/// Whether source file `path` (from debugging symbols) matches `query`, which
+/// matches at path component boundaries. If `whole_file` is set `query` must
+/// be the full file path or a suffix of it, otherwise it may also be a
+/// directory.
+fn path_matches(path: &str, query: &str, whole_file: bool) -> bool {
+    let query = query.trim_start_matches("./");
+    let component_query = format!("/{}", query);
+    match (query.starts_with('/'), whole_file) {
+        (true, true) => path == query,
+        (true, false) => path.starts_with(query),
+        (false, true) => path == query || path.ends_with(&component_query),
+        (false, false) => path.starts_with(query) || path.contains(&component_query),
+    }
+}
+
+/// Index of the DWARF line tables, used to find the functions at a source
+/// location.
+#[derive(Default)]
+pub struct LineIndex {
+    files: Vec<String>,
+    /// (address, file index, line) of each line table row, sorted by address
+    rows: Vec<(u64, u32, u32)>,
+    /// (start address, end address, function), sorted by start address
+    functions: Vec<(u64, u64, FunctionName)>,
+}
+
+impl LineIndex {
+    /// `functions` are (start address, size, function) of the program's
+    /// functions
+    pub fn new<R: gimli::Reader>(
+        context: &addr2line::Context<R>,
+        functions: impl Iterator<Item = (u64, u64, FunctionName)>,
+    ) -> LineIndex {
+        let mut files = Vec::new();
+        let mut file_indices: HashMap<String, u32> = HashMap::new();
+        let mut rows = Vec::new();
+        match context.find_location_range(0, u64::MAX) {
+            Ok(locations) => {
+                for (address, _, location) in locations {
+                    let (file, line) = match (location.file, location.line) {
+                        (Some(file), Some(line)) => (file, line),
+                        _ => continue,
+                    };
+                    let file_index = match file_indices.get(file) {
+                        Some(&index) => index,
+                        None => {
+                            let index = files.len() as u32;
+                            files.push(file.to_string());
+                            file_indices.insert(file.to_string(), index);
+                            index
+                        }
+                    };
+                    rows.push((address, file_index, line));
+                }
+            }
+            Err(err) => log::warn!("Failed to read line tables: {}", err),
+        }
+        rows.sort_unstable();
+
+        let mut functions: Vec<_> = functions
+            .filter(|(address, size, _)| *address != 0 && *size != 0)
+            .map(|(address, size, function)| (address, address + size, function))
+            .collect();
+        functions.sort_unstable_by_key(|(start, end, _)| (*start, *end));
+        log::info!(
+            "Indexed {} line table rows from {} files",
+            rows.len(),
+            files.len()
+        );
+        LineIndex {
+            files,
+            rows,
+            functions,
+        }
+    }
+
+    /// Functions at `query`, ordered by address. For `file:line` queries
+    /// these are the functions containing code for that line (or the next
+    /// line with code, if there is none), which includes functions it is
+    /// inlined into. Otherwise these are the functions starting in a matching
+    /// file or directory.
+    pub fn find_functions(&self, query: &LocationQuery) -> Vec<FunctionName> {
+        let matching_files: Vec<bool> = self
+            .files
+            .iter()
+            .map(|file| path_matches(file, query.path, query.line.is_some()))
+            .collect();
+        let mut functions: Vec<FunctionName> = match query.line {
+            Some(line) => {
+                let rows = || {
+                    self.rows
+                        .iter()
+                        .filter(|(_, file, _)| matching_files[*file as usize])
+                };
+                let code_line = match rows().map(|(_, _, l)| *l).filter(|l| *l >= line).min() {
+                    Some(l) => l,
+                    None => return Vec::new(),
+                };
+                rows()
+                    .filter(|(_, _, l)| *l == code_line)
+                    .filter_map(|(address, _, _)| self.function_containing(*address))
+                    .collect()
+            }
+            None => self
+                .functions
+                .iter()
+                .filter(|(start, _, _)| {
+                    matches!(self.row_at(*start), Some((_, file, _)) if matching_files[*file as usize])
+                })
+                .map(|(_, _, function)| *function)
+                .collect(),
+        };
+        functions.sort_by_key(|f| f.address);
+        functions.dedup();
+        functions
+    }
+
+    /// All source files in the line tables, sorted
+    pub fn files(&self) -> Vec<String> {
+        let mut files = self.files.clone();
+        files.sort();
+        files
+    }
+
+    /// (first line, function) of each function starting in source file
+    /// `file`, sorted by line
+    pub fn function_lines(&self, file: &str) -> Vec<(u32, FunctionName)> {
+        let file_index = match self.files.iter().position(|f| f == file) {
+            Some(index) => index as u32,
+            None => return Vec::new(),
+        };
+        let mut lines: Vec<(u32, FunctionName)> = self
+            .functions
+            .iter()
+            .filter_map(|(start, _, function)| match self.row_at(*start) {
+                Some((_, file, line)) if *file == file_index => Some((*line, *function)),
+                _ => None,
+            })
+            .collect();
+        lines.sort_by_key(|(line, function)| (*line, function.address));
+        lines
+    }
+
+    /// Source file of the code at `address`
+    pub fn source_file(&self, address: u64) -> Option<&str> {
+        self.row_at(address)
+            .map(|(_, file, _)| self.files[*file as usize].as_str())
+    }
+
+    /// The line table row covering `address`
+    fn row_at(&self, address: u64) -> Option<&(u64, u32, u32)> {
+        let index = self.rows.partition_point(|(a, _, _)| *a <= address);
+        index.checked_sub(1).map(|index| &self.rows[index])
+    }
+
+    fn function_containing(&self, address: u64) -> Option<FunctionName> {
+        let index = self
+            .functions
+            .partition_point(|(start, _, _)| *start <= address);
+        if index == 0 {
+            return None;
+        }
+        let (_, end, function) = self.functions[index - 1];
+        if address < end {
+            Some(function)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            parse_query("cache.cc:214"),
+            Some(LocationQuery {
+                path: "cache.cc",
+                line: Some(214)
+            })
+        );
+        assert_eq!(
+            parse_query("src/net/"),
+            Some(LocationQuery {
+                path: "src/net/",
+                line: None
+            })
+        );
+        assert_eq!(
+            parse_query("cache.cc"),
+            Some(LocationQuery {
+                path: "cache.cc",
+                line: None
+            })
+        );
+        assert_eq!(parse_query("Cache::lookup"), None);
+        assert_eq!(parse_query("Cache::lookup(int)"), None);
+        assert_eq!(parse_query("foo::1"), None);
+        assert_eq!(parse_query("cache.cc:"), None);
+        assert_eq!(parse_query("operator/"), None);
+        assert_eq!(parse_query("=src/net/"), None);
+        assert_eq!(parse_query("foo.bar"), None);
+        assert_eq!(parse_query("net/http.ListenAndServe"), None);
+        assert_eq!(parse_query("src/net"), None);
+        assert_eq!(
+            parse_query("src/net/cache.cc"),
+            Some(LocationQuery {
+                path: "src/net/cache.cc",
+                line: None
+            })
+        );
+        assert_eq!(parse_query("/foo/"), None);
+        assert_eq!(parse_query("file:cache.cc:214"), None);
+        assert_eq!(
+            parse_query("/build/src/cache.cc:214"),
+            Some(LocationQuery {
+                path: "/build/src/cache.cc",
+                line: Some(214)
+            })
+        );
+    }
+
+    #[test]
+    fn test_path_matches() {
+        assert!(path_matches("/build/src/cache.cc", "cache.cc", true));
+        assert!(path_matches("/build/src/cache.cc", "src/cache.cc", true));
+        assert!(path_matches(
+            "/build/src/cache.cc",
+            "/build/src/cache.cc",
+            true
+        ));
+        assert!(!path_matches("/build/src/lrucache.cc", "cache.cc", true));
+        assert!(!path_matches("/build/src/cache.cc", "/src/cache.cc", true));
+        assert!(path_matches("/build/src/net/a.cc", "src/net/", false));
+        assert!(path_matches("/build/src/net/a.cc", "./src/net", false));
+        assert!(path_matches("src/net/a.cc", "src/net/", false));
+        assert!(!path_matches("/build/src/internet/a.cc", "net/", false));
+    }
+
+    #[test]
+    fn test_function_lines() {
+        let function = |name, address| FunctionName::new(name, address);
+        let index = LineIndex {
+            files: vec![
+                "/build/src/b.cc".to_string(),
+                "/build/src/a.cc".to_string(),
+                "/build/include/a.h".to_string(),
+            ],
+            rows: vec![
+                (0x100, 0, 10),
+                // `inlined` starts with code inlined from a header
+                (0x110, 2, 3),
+                (0x120, 1, 20),
+                (0x200, 1, 30),
+                (0x210, 0, 11),
+                (0x300, 1, 30),
+            ],
+            functions: vec![
+                (0x100, 0x110, function("b", 0x100)),
+                (0x110, 0x200, function("inlined", 0x110)),
+                (0x200, 0x210, function("same_line2", 0x200)),
+                (0x300, 0x310, function("same_line1", 0x300)),
+            ],
+        };
+
+        assert_eq!(
+            index.files(),
+            vec!["/build/include/a.h", "/build/src/a.cc", "/build/src/b.cc"]
+        );
+        assert_eq!(
+            index.function_lines("/build/src/b.cc"),
+            vec![(10, function("b", 0x100))]
+        );
+        // Functions are listed under the file of their first row, and ones
+        // starting on the same line are ordered by address
+        assert_eq!(
+            index.function_lines("/build/include/a.h"),
+            vec![(3, function("inlined", 0x110))]
+        );
+        assert_eq!(
+            index.function_lines("/build/src/a.cc"),
+            vec![
+                (30, function("same_line2", 0x200)),
+                (30, function("same_line1", 0x300))
+            ]
+        );
+        assert_eq!(index.function_lines("a.cc"), Vec::new());
+    }
+}
@@ -0,0 +1,206 @@
+use object::elf;
+use object::read::elf::{Dyn, FileHeader};
+use object::{Object, ObjectSymbol};
+use std::collections::{HashSet, VecDeque};
+use std::path::Path;
+
+/// Directories searched after any paths specified in the binary or
+/// environment.
+const DEFAULT_DIRS: &[&str] = &[
+    "/lib/x86_64-linux-gnu",
+    "/usr/lib/x86_64-linux-gnu",
+    "/lib/aarch64-linux-gnu",
+    "/usr/lib/aarch64-linux-gnu",
+    "/lib64",
+    "/usr/lib64",
+    "/lib",
+    "/usr/lib",
+];
+
+const LD_SO_CONF: &str = "/etc/ld.so.conf";
+
+#[derive(Default)]
+struct DynamicInfo {
+    needed: Vec<String>,
+    rpath: Vec<String>,
+    runpath: Vec<String>,
+}
+
+/// Returns paths of all shared libraries (transitively) needed by the binary at
+/// `path`, in the (breadth-first) order in which the dynamic linker (ld.so)
+/// would search them for symbols. This approximates ld.so's search, e.g. it
+/// does not read its cache.
+pub fn dependencies(path: &str) -> Vec<String> {
+    let ld_so_conf_dirs = ld_so_conf_dirs(LD_SO_CONF);
+    let ld_library_path: Vec<String> = std::env::var("LD_LIBRARY_PATH")
+        .map(|p| split_path_list(&p, ""))
+        .unwrap_or_default();
+
+    let mut dependencies = Vec::new();
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(path.to_string());
+    while let Some(object_path) = queue.pop_front() {
+        let info = match with_object_data(&object_path, dynamic_info) {
+            Some(Ok(info)) => info,
+            Some(Err(err)) => {
+                log::warn!("Failed to read dynamic section of {}: {}", object_path, err);
+                continue;
+            }
+            None => continue,
+        };
+        let origin = Path::new(&object_path)
+            .parent()
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let expand = |paths: &Vec<String>| -> Vec<String> {
+            paths
+                .iter()
+                .flat_map(|p| split_path_list(p, &origin))
+                .collect()
+        };
+        // DT_RPATH is ignored if DT_RUNPATH is present
+        let rpath = if info.runpath.is_empty() {
+            expand(&info.rpath)
+        } else {
+            Vec::new()
+        };
+        let search_dirs = rpath
+            .iter()
+            .chain(ld_library_path.iter())
+            .chain(expand(&info.runpath).iter())
+            .chain(ld_so_conf_dirs.iter())
+            .cloned()
+            .chain(DEFAULT_DIRS.iter().map(|d| d.to_string()))
+            .collect::<Vec<String>>();
+
+        for needed in info.needed {
+            if !seen.insert(needed.clone()) {
+                continue;
+            }
+            let found = if needed.contains('/') {
+                Some(needed.clone()).filter(|p| Path::new(p).exists())
+            } else {
+                search_dirs
+                    .iter()
+                    .map(|dir| format!("{}/{}", dir, needed))
+                    .find(|p| Path::new(p).exists())
+            };
+            match found {
+                Some(library_path) => {
+                    log::trace!("Resolved {} to {}", needed, library_path);
+                    dependencies.push(library_path.clone());
+                    queue.push_back(library_path);
+                }
+                None => log::warn!("Could not find shared library {}", needed),
+            }
+        }
+    }
+    dependencies
+}
+
+/// Returns the first of `libraries` whose dynamic symbol table defines the
+/// function `name` (which should not have a version suffix).
+pub fn find_definition(libraries: &[String], name: &str) -> Option<String> {
+    libraries
+        .iter()
+        .find(|library| {
+            let defines = with_object_data(library, |data| {
+                object::File::parse(data).map(|file| {
+                    file.dynamic_symbols().any(|symbol| {
+                        symbol.kind() == object::SymbolKind::Text
+                            && !symbol.is_undefined()
+                            && matches!(symbol.name(), Ok(n) if n.split('@').next().unwrap() == name)
+                    })
+                })
+            });
+            matches!(defines, Some(Ok(true)))
+        })
+        .cloned()
+}
+
+/// Call `f` on the contents of the file at `path`. Returns `None` if the file
+/// could not be read.
+fn with_object_data<T, F>(path: &str, f: F) -> Option<T>
+where
+    F: FnOnce(&[u8]) -> T,
+{
+    let file = std::fs::File::open(path).ok()?;
+    let mmap = unsafe { memmap2::Mmap::map(&file) }.ok()?;
+    Some(f(&mmap))
+}
+
+fn dynamic_info(data: &[u8]) -> object::read::Result<DynamicInfo> {
+    let header = elf::FileHeader64::<object::Endianness>::parse(data)?;
+    let endian = header.endian()?;
+    let sections = header.sections(endian, data)?;
+    let mut info = DynamicInfo::default();
+    if let Some((entries, link)) = sections.dynamic(endian, data)? {
+        let strings = sections.strings(endian, data, link)?;
+        for entry in entries {
+            let list = match entry.tag32(endian) {
+                Some(elf::DT_NEEDED) => &mut info.needed,
+                Some(elf::DT_RPATH) => &mut info.rpath,
+                Some(elf::DT_RUNPATH) => &mut info.runpath,
+                _ => continue,
+            };
+            let value = entry.string(endian, strings)?;
+            list.push(String::from_utf8_lossy(value).into_owned());
+        }
+    }
+    Ok(info)
+}
+
+/// Split a `:`-separated list of directories, expanding `$ORIGIN` to `origin`
+fn split_path_list(paths: &str, origin: &str) -> Vec<String> {
+    paths
+        .split(':')
+        .filter(|p| !p.is_empty())
+        .map(|p| p.replace("${ORIGIN}", origin).replace("$ORIGIN", origin))
+        .collect()
+}
+
+/// Directories listed in ld.so.conf, following `include` directives
+fn ld_so_conf_dirs(conf_path: &str) -> Vec<String> {
+    let contents = match std::fs::read_to_string(conf_path) {
+        Ok(contents) => contents,
+        Err(_) => return Vec::new(),
+    };
+    let mut dirs = Vec::new();
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap().trim();
+        if let Some(pattern) = line.strip_prefix("include") {
+            for included in glob_files(pattern.trim()) {
+                dirs.extend(ld_so_conf_dirs(&included));
+            }
+        } else if !line.is_empty() {
+            dirs.push(line.to_string());
+        }
+    }
+    dirs
+}
+
+/// Minimal glob supporting a `*` in the file name only, which is all that is
+/// used by ld.so.conf in practice (e.g. `/etc/ld.so.conf.d/*.conf`).
+fn glob_files(pattern: &str) -> Vec<String> {
+    let path = Path::new(pattern);
+    let (dir, file_pattern) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => (dir, name.to_string_lossy().into_owned()),
+        _ => return Vec::new(),
+    };
+    let (prefix, suffix) = match file_pattern.split_once('*') {
+        Some(parts) => parts,
+        None => return vec![pattern.to_string()],
+    };
+    let mut files: Vec<String> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(prefix) && name.ends_with(suffix))
+            .map(|name| dir.join(name).to_string_lossy().into_owned())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files
+}
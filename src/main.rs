@@ -1,9 +1,19 @@
+mod arch;
 mod bpftrace_compiler;
+mod cache;
 mod controller;
+mod demangle;
 mod error;
 mod events;
+mod history;
+mod line_index;
+mod linker;
+mod process;
 mod program;
+mod query;
 mod search;
+mod source;
+mod symbol_tree;
 mod trace_structs;
 mod tracer;
 mod views;
@@ -15,6 +25,7 @@ use std::env;
 use std::fmt::Write;
 use std::panic::PanicInfo;
 use std::sync::Mutex;
+use trace_structs::TraceTarget;
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
@@ -26,6 +37,15 @@ x - toggle tracing on current line
 X - toggle tracing of an inlined function on current line
 <enter> - push current call onto trace stack
 > (shift+.) - specify arbitrary function to push onto trace stack
+n - browse functions by namespace and push one onto trace stack
+o - open a source file and push one of its functions onto trace stack
+a - push all functions matching a pattern (e.g. template instantiations)
+    onto trace stack as one frame
+p - pin or unpin current function, pinned and recently traced functions are
+    listed before searching
+c - insert a caller of current function below it in trace stack
+i - find the targets of indirect calls on current line and push one onto
+    trace stack
 <esc> - pop function off of trace stack
 r - restart trace, clear current aggregates
 h - get histogram of current function's latency
@@ -39,6 +59,33 @@ lazy_static::lazy_static! {
     static ref PANIC_MESSAGE: Mutex<Option<String>> = Mutex::new(None);
 }
 
+fn get_sources(args: &clap::ArgMatches) -> source::Sources {
+    let values = |name| -> Vec<String> {
+        args.values_of(name)
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default()
+    };
+    let source_map = values("source-map")
+        .iter()
+        .map(|mapping| source::Sources::parse_source_map(mapping).unwrap())
+        .collect();
+    let mut providers: Vec<Box<dyn source::SourceProvider>> = Vec::new();
+    for dir in values("source-dir") {
+        providers.push(Box::new(source::DirectoryProvider::new(Some(dir))));
+    }
+    for archive in values("source-tar") {
+        providers.push(Box::new(source::TarProvider::new(archive)));
+    }
+    for repo in values("source-git") {
+        let (repository, revision) = match repo.rsplit_once(':') {
+            Some((repository, revision)) => (repository.to_string(), revision.to_string()),
+            None => (repo, String::from("HEAD")),
+        };
+        providers.push(Box::new(source::GitProvider::new(repository, revision)));
+    }
+    source::Sources::new(source_map, providers)
+}
+
 fn setup_logging() -> Result<Option<LoggerHandle>, Error> {
     if let Ok(var) = env::var("WACHY_LOG") {
         let logger = Logger::try_with_str(var)?
@@ -56,28 +103,121 @@ fn main() {
         let args = App::new("wachy")
             .version(VERSION)
             .long_about(ABOUT)
+            .arg(
+                Arg::with_name("pid")
+                    .short("p")
+                    .long("pid")
+                    .value_name("PID")
+                    .help("Only trace the process with this pid. PROGRAM may be omitted, in which case the binary is found through /proc/<PID>/exe, and then FUNCTION may be omitted too")
+                    .takes_value(true)
+                    .conflicts_with("COMMAND")
+                    .validator(|pid| {
+                        pid.parse::<u32>()
+                            .map(|_| ())
+                            .map_err(|_| format!("Invalid pid {}", pid))
+                    }),
+            )
+            .arg(
+                Arg::with_name("debug-dir")
+                    .long("debug-dir")
+                    .value_name("DIR")
+                    .help("Additional directory to search for separate debug info files (searched before /usr/lib/debug). Can be specified multiple times")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("source-map")
+                    .long("source-map")
+                    .value_name("FROM=TO")
+                    .help("Rewrite source file paths starting with FROM to start with TO instead, e.g. /tmp/build=/home/me/src. Can be specified multiple times, the first matching prefix is used")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .validator(|mapping| {
+                        source::Sources::parse_source_map(&mapping)
+                            .map(|_| ())
+                            .map_err(|err| err.to_string())
+                    }),
+            )
+            .arg(
+                Arg::with_name("source-dir")
+                    .long("source-dir")
+                    .value_name("DIR")
+                    .help("Directory to read source files from, with paths relative to the compilation directory. Can be specified multiple times")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("source-tar")
+                    .long("source-tar")
+                    .value_name("ARCHIVE")
+                    .help("Tarball (optionally gzip-compressed) to read source files from, with paths relative to the compilation directory. Can be specified multiple times")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("source-git")
+                    .long("source-git")
+                    .value_name("REPO[:REV]")
+                    .help("Local git checkout to read source files from (with `git show REV:PATH`, REV defaults to HEAD), with paths relative to the compilation directory. Can be specified multiple times")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
             .arg(
                 Arg::with_name("PROGRAM")
                     .help("Path of binary to trace")
-                    .required(true),
+                    .required_unless_one(&["pid", "COMMAND"]),
             )
             .arg(
                 Arg::with_name("FUNCTION")
-                    .help("Function to trace")
-                    .required(true),
+                    .help("Function to trace. If omitted, pinned and recently traced functions are listed to select from"),
+            )
+            .arg(
+                Arg::with_name("COMMAND")
+                    .help("Command to launch and trace (after `--`). Probes are attached before it starts, and it is relaunched whenever the trace is restarted. PROGRAM may be omitted, in which case the command's binary is traced, and then FUNCTION may be omitted too")
+                    .multiple(true)
+                    .last(true),
             )
             .get_matches();
 
-        // TODO make absolute
-        let file_arg = args.value_of("PROGRAM").unwrap();
-        let file_path = match std::fs::canonicalize(file_arg) {
-            Ok(path) => path.to_string_lossy().into_owned(),
-            Err(err) => return Err(format!("Failed to find file {}: {}", file_arg, err).into()),
+        let target = match (args.value_of("pid"), args.values_of("COMMAND")) {
+            (Some(pid), _) => TraceTarget::Pid(pid.parse::<u32>().unwrap()),
+            (None, Some(command)) => TraceTarget::Command(command.map(String::from).collect()),
+            (None, None) => TraceTarget::All,
+        };
+        let (file_path, function_name) = match (&target, args.value_of("FUNCTION")) {
+            // At most one positional argument was passed, which must be the
+            // function
+            (TraceTarget::Pid(pid), None) => (
+                process::executable_path(*pid)?,
+                args.value_of("PROGRAM").unwrap_or(""),
+            ),
+            (TraceTarget::Command(command), None) => (
+                process::find_executable(&command[0])?,
+                args.value_of("PROGRAM").unwrap_or(""),
+            ),
+            (_, function_name) => {
+                let file_arg = args.value_of("PROGRAM").unwrap();
+                let file_path = match std::fs::canonicalize(file_arg) {
+                    Ok(path) => path.to_string_lossy().into_owned(),
+                    Err(err) => {
+                        return Err(format!("Failed to find file {}: {}", file_arg, err).into())
+                    }
+                };
+                (file_path, function_name.unwrap_or(""))
+            }
         };
-        let function_name = args.value_of("FUNCTION").unwrap();
 
-        let program = program::Program::new(file_path)?;
-        controller::Controller::run(program, function_name)?;
+        let debug_dirs = args
+            .values_of("debug-dir")
+            .map(|dirs| dirs.map(String::from).collect())
+            .unwrap_or_default();
+        let sources = get_sources(&args);
+        controller::Controller::run(file_path, debug_dirs, sources, function_name, target)?;
         Ok(())
     };
 
@@ -0,0 +1,225 @@
+use crate::error::Error;
+use std::os::unix::fs::MetadataExt;
+
+/// Returns a path to the binary being run by process `pid`, which can be used
+/// both to read the binary and to attach uprobes to it.
+///
+/// The path is resolved through `/proc/<pid>/exe`. The link target is relative
+/// to the process's root directory (which differs from ours if it is running
+/// in a container), so we look it up under `/proc/<pid>/root`. If the binary
+/// has since been deleted or replaced on disk, that path no longer refers to
+/// the running executable, so we fall back to `/proc/<pid>/exe` itself.
+pub fn executable_path(pid: u32) -> Result<String, Error> {
+    let exe_link = format!("/proc/{}/exe", pid);
+    let target = match std::fs::read_link(&exe_link) {
+        Ok(target) => target.to_string_lossy().into_owned(),
+        Err(err) => {
+            return Err(format!("Failed to find executable of process {}: {}", pid, err).into())
+        }
+    };
+    Ok(select_executable_path(
+        &exe_link,
+        &target,
+        &format!("/proc/{}/root", pid),
+    ))
+}
+
+/// See `executable_path`. `target` is the target of `exe_link`, relative to
+/// `root`.
+fn select_executable_path(exe_link: &str, target: &str, root: &str) -> String {
+    // The kernel appends this to the link target once the file is unlinked
+    const DELETED_SUFFIX: &str = " (deleted)";
+    if target.ends_with(DELETED_SUFFIX) {
+        log::warn!("Executable {} has been deleted, using {}", target, exe_link);
+        return exe_link.to_string();
+    }
+
+    let root_path = format!("{}{}", root, target);
+    if is_same_file(&root_path, exe_link) {
+        root_path
+    } else {
+        log::warn!(
+            "Executable {} has been replaced, using {}",
+            target,
+            exe_link
+        );
+        exe_link.to_string()
+    }
+}
+
+/// Returns the path of the binary at `file_path` (as returned by
+/// `executable_path`) as seen by the process running it, which unlike
+/// `file_path` doesn't change when the process is restarted. Other paths are
+/// returned as is.
+pub fn stable_path(file_path: &str) -> String {
+    let rest = match file_path.strip_prefix("/proc/") {
+        Some(rest) => rest,
+        None => return file_path.to_string(),
+    };
+    let (pid, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if pid.is_empty() || !pid.bytes().all(|b| b.is_ascii_digit()) {
+        return file_path.to_string();
+    }
+    if let Some(path) = rest.strip_prefix("/root/") {
+        return format!("/{}", path);
+    }
+    if rest == "/exe" {
+        if let Ok(target) = std::fs::read_link(file_path) {
+            let target = target.to_string_lossy();
+            return target.trim_end_matches(" (deleted)").to_string();
+        }
+    }
+    file_path.to_string()
+}
+
+fn is_same_file(path1: &str, path2: &str) -> bool {
+    match (std::fs::metadata(path1), std::fs::metadata(path2)) {
+        (Ok(m1), Ok(m2)) => m1.dev() == m2.dev() && m1.ino() == m2.ino(),
+        _ => false,
+    }
+}
+
+/// Returns the canonical path of the binary that would be run for `command`,
+/// searching `PATH` if it does not contain a `/` (like a shell would).
+pub fn find_executable(command: &str) -> Result<String, Error> {
+    let candidates: Vec<std::path::PathBuf> = if command.contains('/') {
+        vec![command.into()]
+    } else {
+        std::env::var_os("PATH")
+            .map(|paths| {
+                std::env::split_paths(&paths)
+                    .map(|dir| dir.join(command))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    candidates
+        .iter()
+        .find_map(|path| std::fs::canonicalize(path).ok())
+        .map(|path| path.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("Failed to find executable for command {}", command).into())
+}
+
+/// Returns paths of all files mapped into the address space of process `pid`
+/// (i.e. the executable and its loaded shared libraries), in the order they
+/// are mapped.
+pub fn mapped_files(pid: u32) -> Result<Vec<String>, Error> {
+    let maps_path = format!("/proc/{}/maps", pid);
+    match std::fs::read_to_string(&maps_path) {
+        Ok(maps) => Ok(parse_maps(pid, &maps)),
+        Err(err) => Err(format!("Failed to read {}: {}", maps_path, err).into()),
+    }
+}
+
+/// Paths of the files in `maps`, the contents of `/proc/<pid>/maps`
+fn parse_maps(pid: u32, maps: &str) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+    for line in maps.lines() {
+        // Format is `address perms offset dev inode pathname`, where pathname
+        // may contain spaces.
+        let path = match line.splitn(6, ' ').nth(5).map(|p| p.trim_start()) {
+            Some(path) if path.starts_with('/') => path,
+            _ => continue,
+        };
+        if path.ends_with(" (deleted)") {
+            continue;
+        }
+        // Paths are relative to the process's root directory
+        let path = format!("/proc/{}/root{}", pid, path);
+        if !files.contains(&path) {
+            files.push(path);
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_executable_path() {
+        let pid = std::process::id();
+        let exe = std::fs::canonicalize("/proc/self/exe").unwrap();
+        assert_eq!(
+            executable_path(pid).unwrap(),
+            format!("/proc/{}/root{}", pid, exe.display())
+        );
+    }
+
+    #[test]
+    fn test_stable_path() {
+        let pid = std::process::id();
+        let exe = std::fs::read_link("/proc/self/exe").unwrap();
+        let exe = exe.to_str().unwrap();
+        assert_eq!(stable_path(&format!("/proc/{}/exe", pid)), exe);
+        assert_eq!(
+            stable_path(&format!("/proc/{}/root/usr/bin/foo", pid)),
+            "/usr/bin/foo"
+        );
+        assert_eq!(stable_path("/usr/bin/foo"), "/usr/bin/foo");
+        assert_eq!(stable_path("/proc/self/root/foo"), "/proc/self/root/foo");
+    }
+
+    #[test]
+    fn test_executable_path_deleted() {
+        // Run a copy of `sleep` and delete it while it is running
+        let dir = std::env::temp_dir().join(format!("wachy_test_deleted_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sleep");
+        std::fs::copy(find_executable("sleep").unwrap(), &path).unwrap();
+        let mut child = Command::new(&path).arg("10").spawn().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let result = executable_path(child.id());
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(result.unwrap(), format!("/proc/{}/exe", child.id()));
+    }
+
+    #[test]
+    fn test_select_executable_path() {
+        let root = std::env::temp_dir().join(format!("wachy_test_root_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        let root_str = root.to_str().unwrap();
+        let running = root.join("running");
+        std::fs::write(&running, "running").unwrap();
+        std::fs::hard_link(&running, root.join("bin/same")).unwrap();
+        std::fs::write(root.join("bin/replaced"), "replaced").unwrap();
+        let exe_link = running.to_str().unwrap();
+
+        assert_eq!(
+            select_executable_path(exe_link, "/bin/same", root_str),
+            format!("{}/bin/same", root_str)
+        );
+        assert_eq!(
+            select_executable_path(exe_link, "/bin/replaced", root_str),
+            exe_link
+        );
+        assert_eq!(
+            select_executable_path(exe_link, "/bin/same (deleted)", root_str),
+            exe_link
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_parse_maps() {
+        let maps = "\
+55d4c5a00000-55d4c5a02000 r--p 00000000 fd:01 1234                       /usr/bin/my prog
+55d4c5a02000-55d4c5a06000 r-xp 00002000 fd:01 1234                       /usr/bin/my prog
+55d4c6f00000-55d4c6f21000 rw-p 00000000 00:00 0                          [heap]
+7f0e1a000000-7f0e1a022000 r--p 00000000 fd:01 5678                       /usr/lib/libc.so.6
+7f0e1a200000-7f0e1a201000 r-xp 00000000 fd:01 9012                       /tmp/old.so (deleted)
+7f0e1a300000-7f0e1a301000 rw-p 00000000 00:00 0 
+7ffd3c1e0000-7ffd3c201000 rw-p 00000000 00:00 0                          [stack]
+";
+        assert_eq!(
+            parse_maps(42, maps),
+            vec![
+                "/proc/42/root/usr/bin/my prog".to_string(),
+                "/proc/42/root/usr/lib/libc.so.6".to_string(),
+            ]
+        );
+    }
+}
@@ -1,56 +1,328 @@
+use crate::arch::{self, Architecture, BranchTarget};
+use crate::cache::Cache;
+use crate::demangle;
 use crate::error::Error;
+use crate::line_index::{LineIndex, LocationQuery};
+use crate::query;
 use addr2line::fallible_iterator::FallibleIterator;
 use addr2line::Location;
+use gimli::Reader;
 use object::read::File;
 use object::Object;
 use object::ObjectSection;
+use object::ObjectSegment;
 use object::ObjectSymbol;
 use object::ObjectSymbolTable;
 use std::borrow::Cow;
-use std::collections::{hash_map, HashMap};
+use std::collections::{hash_map, HashMap, HashSet};
+use std::convert::TryInto;
 use std::fmt;
 use std::io::ErrorKind;
 use std::io::Read;
+use std::iter;
+use std::path::Path;
 use std::sync::Arc;
-use zydis::ffi::Decoder;
-use zydis::formatter::{Formatter, OutputBuffer};
-use zydis::{
-    enums::generated::{AddressWidth, FormatterStyle, MachineMode, Mnemonic},
-    DecodedInstruction,
-};
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
-/// Name corresponding to a function symbol that exists in the program
-pub struct FunctionName(pub &'static str);
+/// A function symbol that exists in the program. Local symbols (e.g. C
+/// `static` functions) in different compilation units can share a name, so
+/// functions are identified by name and address.
+pub struct FunctionName {
+    pub name: &'static str,
+    /// 0 for undefined (dynamic) symbols
+    pub address: u64,
+    /// Whether another function defined in the program has the same name
+    ambiguous: bool,
+    /// Whether the function has a symbol table entry, otherwise it is only
+    /// known from debug info
+    in_symbol_table: bool,
+}
+
+impl FunctionName {
+    pub(crate) fn new(name: &'static str, address: u64) -> FunctionName {
+        FunctionName {
+            name,
+            address,
+            ambiguous: false,
+            in_symbol_table: true,
+        }
+    }
+
+    pub fn is_ambiguous(&self) -> bool {
+        self.ambiguous
+    }
+
+    pub fn is_in_symbol_table(&self) -> bool {
+        self.in_symbol_table
+    }
+
+    /// The same function, as if it was only known from debug info
+    #[cfg(test)]
+    pub(crate) fn without_symbol(self) -> FunctionName {
+        FunctionName {
+            in_symbol_table: false,
+            ..self
+        }
+    }
+
+    /// The bpftrace uprobe target for this function (plus `offset`). Uses the
+    /// address if the name is ambiguous, since bpftrace would otherwise pick
+    /// an arbitrary one of the functions, or if bpftrace can't look up the
+    /// name in the symbol table.
+    pub fn probe_target(&self, offset: u32) -> String {
+        match (self.ambiguous || !self.in_symbol_table, offset) {
+            (true, _) => format!("{:#x}", self.address + offset as u64),
+            (false, 0) => self.name.to_string(),
+            (false, _) => format!("{}+{}", self.name, offset),
+        }
+    }
+}
 
 impl fmt::Display for FunctionName {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let demangled = cplus_demangle::demangle(self.0).unwrap_or(String::from(self.0));
+        let demangled = demangle::demangle(self.name, None).unwrap_or(String::from(self.name));
         fmt::Display::fmt(&demangled, f)
     }
 }
 
 impl fmt::Debug for FunctionName {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(self.0, f)
+        fmt::Display::fmt(self.name, f)
+    }
+}
+
+/// Value of a C++ vtable entry
+#[derive(PartialEq)]
+enum VtableEntry {
+    Address(u64),
+    /// Address of a dynamic symbol, filled in by a relocation
+    Symbol(object::SymbolIndex),
+}
+
+/// Map from function address to (caller, call instruction address)
+pub type CallersIndex = HashMap<u64, Vec<(FunctionName, u64)>>;
+
+/// Symbol information derived from the program's symbol tables and debug
+/// info
+struct SymbolTables {
+    symbols: Vec<SymbolInfo>,
+    vtables: Vec<(u64, u64)>,
+    got_symbols_map: HashMap<u64, FunctionName>,
+    dynamic_symbols_map: HashMap<u64, FunctionName>,
+}
+
+/// Name of the `SymbolTables` cache entry
+const SYMBOLS_CACHE: &str = "symbols";
+/// Name of the `CallersIndex` cache entry
+const CALLERS_CACHE: &str = "callers";
+
+/// Serializable version of `SymbolTables`
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedSymbolTables {
+    symbols: Vec<CachedSymbol>,
+    vtables: Vec<(u64, u64)>,
+    got_symbols: Vec<(u64, CachedFunctionName)>,
+    dynamic_symbols: Vec<(u64, CachedFunctionName)>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedFunctionName {
+    name: String,
+    address: u64,
+    ambiguous: bool,
+    in_symbol_table: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedSymbol {
+    function: CachedFunctionName,
+    demangled_name: Option<String>,
+    section_index: Option<usize>,
+    size: u64,
+    source_file: Option<String>,
+}
+
+/// Serializable version of `CallersIndex`, as (function address, [(caller
+/// address, call instruction address)])
+type CachedCallersIndex = Vec<(u64, Vec<(u64, u64)>)>;
+
+/// Builds the callers index of a program, see `Program::callers_indexer`
+pub struct CallersIndexer {
+    arch: &'static dyn Architecture,
+    /// (function, start address of the function or a separate part of it,
+    /// code of the function or part)
+    functions: Vec<(FunctionName, u64, &'static [u8])>,
+    /// Map from the start address of each function or separate part to the
+    /// function
+    function_starts: HashMap<u64, FunctionName>,
+}
+
+impl CallersIndexer {
+    /// Decode all functions to find the calls to each one. Jumps to the start
+    /// of another function are tail calls, so they are included too.
+    /// `progress` is called with the percentage of functions decoded.
+    pub fn run<F: FnMut(u32)>(self, mut progress: F) -> CallersIndex {
+        let start_time = std::time::Instant::now();
+        let mut callers = CallersIndex::new();
+        let mut percent = 0;
+        for (i, &(function, address, code)) in self.functions.iter().enumerate() {
+            let calls = self
+                .arch
+                .calls(address, code)
+                .into_iter()
+                .filter_map(|call| match call.target {
+                    BranchTarget::Address(target) => Some((target, call.address)),
+                    _ => None,
+                });
+            let tail_calls =
+                self.arch
+                    .jumps(address, code)
+                    .into_iter()
+                    .filter_map(|jump| match jump.target {
+                        BranchTarget::Address(target) => match self.function_starts.get(&target) {
+                            // Jumps within the function (including its separate
+                            // parts) aren't calls
+                            Some(&f) if f != function => Some((target, jump.address)),
+                            _ => None,
+                        },
+                        _ => None,
+                    });
+            for (target, ip) in calls.chain(tail_calls) {
+                callers.entry(target).or_default().push((function, ip));
+            }
+            let new_percent = ((i + 1) * 100 / self.functions.len()) as u32;
+            if new_percent != percent {
+                percent = new_percent;
+                progress(percent);
+            }
+        }
+        for function_callers in callers.values_mut() {
+            function_callers.sort_by_key(|(function, ip)| (function.name, *ip));
+        }
+        log::info!(
+            "Indexed calls to {} functions in {:?}",
+            callers.len(),
+            start_time.elapsed()
+        );
+        callers
+    }
+}
+
+/// Builds the line index of a program, see `Program::line_indexer`
+pub struct LineIndexer {
+    /// Path of the program or separate debug file containing the line tables
+    file_path: String,
+    /// (start address, size, function) of the program's functions
+    functions: Vec<(u64, u64, FunctionName)>,
+}
+
+impl LineIndexer {
+    /// Read all line tables. Failures are only logged, giving an empty
+    /// index.
+    pub fn run(self) -> LineIndex {
+        let start_time = std::time::Instant::now();
+        let context = std::fs::File::open(&self.file_path)
+            .and_then(|file| unsafe { memmap2::Mmap::map(&file) })
+            .map_err(|err| err.to_string())
+            .and_then(|mmap| {
+                let file = object::File::parse(&*mmap).map_err(|err| err.to_string())?;
+                new_context(&file).map_err(|err| err.to_string())
+            });
+        let line_index = match context {
+            Ok(context) => LineIndex::new(&context, self.functions.into_iter()),
+            Err(err) => {
+                log::warn!("Failed to read line tables of {}: {}", self.file_path, err);
+                LineIndex::default()
+            }
+        };
+        log::info!("Indexed line tables in {:?}", start_time.elapsed());
+        line_index
     }
 }
 
 pub struct Program {
     /// Only used when printing error messages
     pub file_path: String,
+    /// Additional directories searched for separate debug info files
+    debug_dirs: Vec<String>,
     file: File<'static>,
+    arch: &'static dyn Architecture,
     name_to_symbol: Arc<HashMap<FunctionName, SymbolInfo>>,
     address_to_name: HashMap<u64, FunctionName>,
+    // Map from function name to the `.cold` parts split off from functions of
+    // that name (sorted by address), see `get_function_parts`
+    function_parts: HashMap<&'static str, Vec<FunctionName>>,
+    /// Separate debug file containing the line tables, if any
+    debug_file_path: Option<String>,
+    // Built in the background after loading since it requires reading all
+    // line tables, see `line_indexer`
+    line_index: Option<Arc<LineIndex>>,
     context: addr2line::Context<gimli::EndianArcSlice<gimli::RunTimeEndian>>,
     // (start_address, size) of runtime addresses for dynamic symbols (functions
     // loaded from shared libraries)
-    dynamic_symbols_ranges: Vec<std::ops::Range<u64>>,
+    dynamic_symbols_ranges: Arc<Vec<std::ops::Range<u64>>>,
     dynamic_symbols_map: HashMap<u64, FunctionName>,
+    // Map from GOT entry address to the dynamic symbol it is relocated to
+    got_symbols_map: HashMap<u64, FunctionName>,
+    // (address, size) of C++ vtables (`_ZTV` symbols)
+    vtables: Vec<(u64, u64)>,
+    // Index of all direct calls in the program. Built in the background when
+    // first needed since it requires decoding all code, see `callers_indexer`.
+    callers: Option<CallersIndex>,
+    cache: Option<Cache>,
 }
 
+/// Progress of `Program::load`
+pub enum LoadProgress {
+    /// Loading has moved on to the described stage
+    Stage(&'static str),
+    /// Function symbols are available for searching. Sent before debug info is
+    /// read, so this excludes functions only described in debug info, and
+    /// ambiguous names are not yet marked.
+    Symbols(SymbolsGenerator),
+}
+
+#[derive(Default)]
 pub struct SymbolsGenerator {
     name_to_symbol: Arc<HashMap<FunctionName, SymbolInfo>>,
+    /// None until the line tables have been indexed
+    line_index: Option<Arc<LineIndex>>,
+    /// See `Program::dynamic_symbols_ranges`
+    dynamic_symbols_ranges: Arc<Vec<std::ops::Range<u64>>>,
+}
+
+impl SymbolsGenerator {
+    pub(crate) fn new(
+        symbols: &[SymbolInfo],
+        dynamic_symbols_ranges: &Arc<Vec<std::ops::Range<u64>>>,
+    ) -> SymbolsGenerator {
+        SymbolsGenerator {
+            name_to_symbol: Arc::new(symbols.iter().map(|s| (s.name, s.clone())).collect()),
+            line_index: None,
+            dynamic_symbols_ranges: Arc::clone(dynamic_symbols_ranges),
+        }
+    }
+
+    /// Properties of `symbol` for evaluating search queries
+    pub fn fields<'a>(&'a self, symbol: &'a SymbolInfo) -> SymbolFields<'a> {
+        SymbolFields {
+            symbol,
+            symbols: self,
+        }
+    }
+
+    /// Symbols of the functions at source location `query`, or None if the
+    /// line tables haven't been indexed yet
+    pub fn find_location(&self, query: &LocationQuery) -> Option<Vec<SymbolInfo>> {
+        let line_index = self.line_index.as_ref()?;
+        Some(
+            line_index
+                .find_functions(query)
+                .into_iter()
+                .filter_map(|function| self.name_to_symbol.get(&function).cloned())
+                .collect(),
+        )
+    }
 }
 
 impl<'a> IntoIterator for &'a SymbolsGenerator {
@@ -61,6 +333,39 @@ impl<'a> IntoIterator for &'a SymbolsGenerator {
     }
 }
 
+/// See `SymbolsGenerator::fields`
+pub struct SymbolFields<'a> {
+    symbol: &'a SymbolInfo,
+    symbols: &'a SymbolsGenerator,
+}
+
+impl query::Fields for SymbolFields<'_> {
+    fn name(&self) -> &str {
+        self.symbol.as_ref()
+    }
+
+    /// Only known once the line tables have been indexed
+    fn source_file(&self) -> Option<&str> {
+        self.symbols
+            .line_index
+            .as_ref()?
+            .source_file(self.symbol.address)
+    }
+
+    fn size(&self) -> u64 {
+        self.symbol.size
+    }
+
+    fn is_dynamic(&self) -> bool {
+        self.symbol.address == 0
+            || self
+                .symbols
+                .dynamic_symbols_ranges
+                .iter()
+                .any(|r| r.contains(&self.symbol.address))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SymbolInfo {
     pub name: FunctionName,
@@ -68,13 +373,31 @@ pub struct SymbolInfo {
     section_index: Option<object::SectionIndex>,
     address: u64,
     size: u64,
+    /// Source file of the function, only set if its name is ambiguous
+    source_file: Option<String>,
 }
 
 impl AsRef<str> for SymbolInfo {
     fn as_ref(&self) -> &str {
         match &self.demangled_name {
             Some(dn) => &dn,
-            None => self.name.0,
+            None => self.name.name,
+        }
+    }
+}
+
+impl SymbolInfo {
+    /// Symbol of a function of unknown size, named `name` (which is
+    /// displayed as is, rather than demangled)
+    #[cfg(test)]
+    pub(crate) fn new(name: &'static str, address: u64) -> SymbolInfo {
+        SymbolInfo {
+            name: FunctionName::new(name, address),
+            demangled_name: None,
+            section_index: None,
+            address,
+            size: 0,
+            source_file: None,
         }
     }
 }
@@ -86,7 +409,103 @@ impl fmt::Display for SymbolInfo {
             // Undefined symbol
             fmt::Display::fmt("(D) ", f)?
         }
-        fmt::Display::fmt(self.as_ref(), f)
+        fmt::Display::fmt(self.as_ref(), f)?;
+        if let Some(source_file) = &self.source_file {
+            write!(f, " ({})", source_file)?;
+        }
+        Ok(())
+    }
+}
+
+/// Standard location of separate debug info files
+const SYSTEM_DEBUG_DIR: &str = "/usr/lib/debug";
+
+impl FunctionName {
+    fn to_cached(self) -> CachedFunctionName {
+        CachedFunctionName {
+            name: self.name.to_string(),
+            address: self.address,
+            ambiguous: self.ambiguous,
+            in_symbol_table: self.in_symbol_table,
+        }
+    }
+
+    /// Names are leaked since they are static, like those in the mmapped
+    /// file. `names` is used to only leak each name once.
+    fn from_cached(
+        cached: CachedFunctionName,
+        names: &mut HashMap<String, &'static str>,
+    ) -> FunctionName {
+        let name = *names
+            .entry(cached.name)
+            .or_insert_with_key(|name| Box::leak(name.clone().into_boxed_str()));
+        FunctionName {
+            name,
+            address: cached.address,
+            ambiguous: cached.ambiguous,
+            in_symbol_table: cached.in_symbol_table,
+        }
+    }
+}
+
+impl SymbolTables {
+    fn to_cached(&self) -> CachedSymbolTables {
+        CachedSymbolTables {
+            symbols: self
+                .symbols
+                .iter()
+                .map(|s| CachedSymbol {
+                    function: s.name.to_cached(),
+                    demangled_name: s.demangled_name.clone(),
+                    section_index: s.section_index.map(|index| index.0),
+                    size: s.size,
+                    source_file: s.source_file.clone(),
+                })
+                .collect(),
+            vtables: self.vtables.clone(),
+            got_symbols: self
+                .got_symbols_map
+                .iter()
+                .map(|(&address, f)| (address, f.to_cached()))
+                .collect(),
+            dynamic_symbols: self
+                .dynamic_symbols_map
+                .iter()
+                .map(|(&address, f)| (address, f.to_cached()))
+                .collect(),
+        }
+    }
+
+    fn from_cached(cached: CachedSymbolTables) -> SymbolTables {
+        let mut names = HashMap::new();
+        SymbolTables {
+            symbols: cached
+                .symbols
+                .into_iter()
+                .map(|s| {
+                    let name = FunctionName::from_cached(s.function, &mut names);
+                    SymbolInfo {
+                        name,
+                        demangled_name: s.demangled_name,
+                        section_index: s.section_index.map(object::SectionIndex),
+                        address: name.address,
+                        size: s.size,
+                        source_file: s.source_file,
+                    }
+                })
+                .collect(),
+            vtables: cached.vtables,
+            got_symbols_map: cached
+                .got_symbols
+                .into_iter()
+                .map(|(address, f)| (address, FunctionName::from_cached(f, &mut names)))
+                .collect(),
+            dynamic_symbols_map: cached
+                .dynamic_symbols
+                .into_iter()
+                .map(|(address, f)| (address, FunctionName::from_cached(f, &mut names)))
+                .collect(),
+        }
     }
 }
 
@@ -95,35 +514,60 @@ fn should_log_verbose() -> bool {
 }
 
 impl Program {
-    pub fn new(file_path: String) -> Result<Self, Error> {
+    /// `debug_dirs` are additional directories to search for separate debug
+    /// info files, see `get_debug_file`.
+    pub fn new(file_path: String, debug_dirs: Vec<String>) -> Result<Self, Error> {
+        let mut program = Program::load(file_path, debug_dirs, |_| ())?;
+        if let Some(indexer) = program.line_indexer() {
+            program.set_line_index(indexer.run());
+        }
+        Ok(program)
+    }
+
+    /// Same as `new`, but reports progress to `progress` as loading proceeds,
+    /// and doesn't build the line index (see `line_indexer`). Intended to be
+    /// run on a separate thread for large programs.
+    pub fn load<F>(
+        file_path: String,
+        debug_dirs: Vec<String>,
+        mut progress: F,
+    ) -> Result<Self, Error>
+    where
+        F: FnMut(LoadProgress),
+    {
+        progress(LoadProgress::Stage("Reading program"));
         let file = Program::parse(&file_path)?;
+        let arch = arch::for_file(&file)
+            .map_err(|err| format!("Failed to load program {}: {}", file_path, err))?;
 
         // TODO fixup unwraps
-        let dynamic_symbols_ranges = file
-            .sections()
-            .filter(|s| s.name().unwrap().starts_with(".plt")) // Include .plt and .plt.got
-            .map(|s| std::ops::Range {
-                start: s.address(),
-                end: s.address() + s.size(),
-            })
-            .collect();
-
-        let mut versioned_symbols_map: HashMap<String, FunctionName> = HashMap::new();
+        let dynamic_symbols_ranges = Arc::new(
+            file.sections()
+                .filter(|s| s.name().unwrap().starts_with(".plt")) // Include .plt and .plt.got
+                .map(|s| std::ops::Range {
+                    start: s.address(),
+                    end: s.address() + s.size(),
+                })
+                .collect(),
+        );
 
         // Try to find file containing `.debug_line` section - if it's not in
         // the passed in binary, check debuglink.
+        progress(LoadProgress::Stage("Finding debug info"));
         let debug_file;
+        let mut debug_file_path = None;
         let debug_file_ref = match file.section_by_name(".debug_line") {
             Some(_) => &file,
-            None => match Program::get_debug_file(&file, &file_path) {
+            None => match Program::get_debug_file(&file, &file_path, &debug_dirs) {
                 None => {
                     return Err(Error::from(format!(
-                        "Program {} is missing debug symbols (section .debug_line not found)",
+                        "Program {} is missing debug symbols (section .debug_line not found, and no separate debug file found - use --debug-dir to specify where to look for it)",
                         file_path
                     )))
                 }
                 Some(r) => match r {
-                    Ok(df) => {
+                    Ok((path, df)) => {
+                        debug_file_path = Some(path);
                         debug_file = df;
                         &debug_file
                     }
@@ -144,58 +588,185 @@ impl Program {
             debug_file_ref
         };
 
-        // if binary contains symbols, use those - if not, get them from the debuginfo file
-        let symbols: Vec<SymbolInfo> = symbols_file
-            .symbols()
-            .filter(|symbol| symbol.kind() == object::SymbolKind::Text) // Filter to functions
-            .map(|symbol| {
-                symbol.name().map(|name| {
-                    let demangled_name = cplus_demangle::demangle(name).ok();
-                    let function = FunctionName(name);
-                    if name.contains("@@") {
-                        versioned_symbols_map
-                            .insert(name.split("@@").next().unwrap().to_string(), function);
-                    }
-                    SymbolInfo {
-                        name: function,
-                        demangled_name,
-                        section_index: symbol.section_index(),
-                        address: symbol.address(),
-                        size: symbol.size(),
-                    }
-                })
-            })
-            .flat_map(|x| {
-                if should_log_verbose() {
-                    log::trace!("{:?}", x);
+        progress(LoadProgress::Stage("Reading symbols"));
+        let cache = Cache::new(&file, &file_path, debug_file_path.as_deref());
+        let cached_tables = cache
+            .as_ref()
+            .and_then(|c| c.read::<CachedSymbolTables>(SYMBOLS_CACHE));
+        let (
+            SymbolTables {
+                symbols,
+                vtables,
+                got_symbols_map,
+                dynamic_symbols_map,
+            },
+            context,
+        ) = match cached_tables {
+            Some(cached_tables) => {
+                let tables = SymbolTables::from_cached(cached_tables);
+                progress(LoadProgress::Symbols(SymbolsGenerator::new(
+                    &tables.symbols,
+                    &dynamic_symbols_ranges,
+                )));
+                progress(LoadProgress::Stage("Reading debug info"));
+                let context = new_context(debug_file_ref).map_err(|err| {
+                    format!("Failed to read debug info of {}: {}", file_path, err)
+                })?;
+                (tables, context)
+            }
+            None => {
+                let (tables, context) = Program::symbol_tables(
+                    &file,
+                    symbols_file,
+                    debug_file_ref,
+                    arch,
+                    &file_path,
+                    &dynamic_symbols_ranges,
+                    &mut progress,
+                )?;
+                if let Some(cache) = &cache {
+                    cache.write(SYMBOLS_CACHE, &tables.to_cached());
                 }
-                x
-            })
-            .collect();
-
-        let dynamic_symbols_map = Program::dynamic_symbols_map(&file, &versioned_symbols_map);
+                (tables, context)
+            }
+        };
 
+        progress(LoadProgress::Stage("Indexing functions"));
         let name_to_symbol: HashMap<_, _> = symbols.into_iter().map(|si| (si.name, si)).collect();
 
         let address_to_name: HashMap<_, _> = name_to_symbol
             .iter()
             .filter(|(_, s)| s.address != 0)
-            .map(|(n, s)| (s.address, n.clone()))
+            .map(|(n, s)| (s.address, *n))
             .collect();
 
-        let context = new_context(debug_file_ref).unwrap();
+        let mut function_parts: HashMap<_, Vec<_>> = HashMap::new();
+        for &part in address_to_name.values() {
+            if let Some(name) = cold_part_base(part.name) {
+                function_parts.entry(name).or_default().push(part);
+            }
+        }
+        for parts in function_parts.values_mut() {
+            parts.sort_by_key(|f| f.address);
+        }
 
         Ok(Program {
             file_path,
+            debug_dirs,
             file,
+            arch,
             name_to_symbol: Arc::new(name_to_symbol),
             address_to_name,
+            function_parts,
+            debug_file_path,
+            line_index: None,
             context,
             dynamic_symbols_ranges,
             dynamic_symbols_map,
+            got_symbols_map,
+            vtables,
+            callers: None,
+            cache,
         })
     }
 
+    /// Read symbols from `symbols_file` (which may be `file` or its debug
+    /// info file), along with any MiniDebugInfo and debug info functions, and
+    /// derive the symbol tables from them. The line info context for
+    /// `debug_file` is only built once the symbols read so far have been
+    /// reported, since that is slow for large programs.
+    fn symbol_tables(
+        file: &File<'static>,
+        symbols_file: &File<'static>,
+        debug_file: &File<'static>,
+        arch: &dyn Architecture,
+        file_path: &str,
+        dynamic_symbols_ranges: &Arc<Vec<std::ops::Range<u64>>>,
+        progress: &mut dyn FnMut(LoadProgress),
+    ) -> Result<
+        (
+            SymbolTables,
+            addr2line::Context<gimli::EndianArcSlice<gimli::RunTimeEndian>>,
+        ),
+        Error,
+    > {
+        // if binary contains symbols, use those - if not, get them from the debuginfo file
+        let mut symbols = Program::function_symbols(symbols_file);
+        let vtables = symbols_file
+            .symbols()
+            .filter(|symbol| {
+                symbol.kind() == object::SymbolKind::Data
+                    && matches!(symbol.name(), Ok(name) if name.starts_with("_ZTV"))
+            })
+            .map(|symbol| (symbol.address(), symbol.size()))
+            .collect();
+
+        // Stripped binaries may still contain function symbols in
+        // MiniDebugInfo, merge in any we don't already have.
+        match Program::get_mini_debug_info(file) {
+            None => (),
+            Some(Ok(mini_debug_info)) => {
+                log::info!("Using symbols from .gnu_debugdata");
+                let mut names: HashSet<_> = symbols.iter().map(|s| s.name).collect();
+                for mut symbol in Program::function_symbols(&mini_debug_info) {
+                    if names.insert(symbol.name) {
+                        // Section indices refer to the embedded file, not
+                        // this one
+                        symbol.section_index = None;
+                        symbols.push(symbol);
+                    }
+                }
+            }
+            Some(Err(err)) => {
+                log::warn!("Ignoring .gnu_debugdata of program {}: {}", file_path, err)
+            }
+        }
+
+        progress(LoadProgress::Symbols(SymbolsGenerator::new(
+            &symbols,
+            dynamic_symbols_ranges,
+        )));
+
+        progress(LoadProgress::Stage("Reading debug info"));
+        let context = new_context(debug_file)
+            .map_err(|err| format!("Failed to read debug info of {}: {}", file_path, err))?;
+        let symbol_addresses: HashSet<u64> = symbols.iter().map(|s| s.address).collect();
+        match Program::debug_info_symbols(&context, &symbol_addresses) {
+            Ok(debug_info_symbols) => {
+                log::info!(
+                    "Found {} functions only described in debug info",
+                    debug_info_symbols.len()
+                );
+                symbols.extend(debug_info_symbols);
+            }
+            Err(err) => log::warn!("Failed to read functions from debug info: {}", err),
+        }
+
+        // Symbol tables may contain duplicate entries
+        let mut seen: HashSet<_> = HashSet::new();
+        symbols.retain(|s| seen.insert(s.name));
+        Program::mark_ambiguous_symbols(&mut symbols, &context);
+
+        // Map from unversioned symbol name to the versioned one
+        let versioned_symbols_map: HashMap<_, _> = symbols
+            .iter()
+            .filter(|s| s.name.name.contains("@@"))
+            .map(|s| (s.name.name.split("@@").next().unwrap().to_string(), s.name))
+            .collect();
+        let got_symbols_map = Program::got_symbols_map(file, &versioned_symbols_map);
+        let dynamic_symbols_map = Program::dynamic_symbols_map(file, arch, &got_symbols_map);
+
+        Ok((
+            SymbolTables {
+                symbols,
+                vtables,
+                got_symbols_map,
+                dynamic_symbols_map,
+            },
+            context,
+        ))
+    }
+
     fn parse(file_path: &String) -> Result<File<'static>, Error> {
         let file = match std::fs::File::open(&file_path) {
             Ok(file) => file,
@@ -217,10 +788,193 @@ impl Program {
         }
     }
 
+    /// Returns all function symbols in `file`
+    fn function_symbols(file: &File<'static>) -> Vec<SymbolInfo> {
+        file.symbols()
+            .filter(|symbol| symbol.kind() == object::SymbolKind::Text) // Filter to functions
+            .map(|symbol| {
+                symbol.name().map(|name| {
+                    let demangled_name = demangle::demangle(name, None);
+                    SymbolInfo {
+                        name: FunctionName::new(name, symbol.address()),
+                        demangled_name,
+                        section_index: symbol.section_index(),
+                        address: symbol.address(),
+                        size: symbol.size(),
+                        source_file: None,
+                    }
+                })
+            })
+            .flat_map(|x| {
+                if should_log_verbose() {
+                    log::trace!("{:?}", x);
+                }
+                x
+            })
+            .collect()
+    }
+
+    /// Returns functions described by `DW_TAG_subprogram` entries in debug
+    /// info that have no symbol (e.g. in partially stripped binaries, or
+    /// after LTO localizes symbols), i.e. whose address is not in
+    /// `symbol_addresses`.
+    fn debug_info_symbols(
+        context: &addr2line::Context<gimli::EndianArcSlice<gimli::RunTimeEndian>>,
+        symbol_addresses: &HashSet<u64>,
+    ) -> Result<Vec<SymbolInfo>, gimli::Error> {
+        let dwarf = context.dwarf();
+        let mut symbols = Vec::new();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let mut language = None;
+            let mut entries = unit.entries();
+            while let Some((_, entry)) = entries.next_dfs()? {
+                if entry.tag() == gimli::DW_TAG_compile_unit {
+                    if let Some(gimli::AttributeValue::Language(lang)) =
+                        entry.attr_value(gimli::DW_AT_language)?
+                    {
+                        language = Some(lang);
+                    }
+                }
+                if entry.tag() != gimli::DW_TAG_subprogram {
+                    continue;
+                }
+                let address = match entry.attr_value(gimli::DW_AT_low_pc)? {
+                    Some(low_pc) => dwarf.attr_address(&unit, low_pc)?,
+                    None => None,
+                };
+                // Functions discarded by the linker may be left with address
+                // 0
+                let address = match address {
+                    Some(address) if address != 0 && !symbol_addresses.contains(&address) => {
+                        address
+                    }
+                    _ => continue,
+                };
+                // High PC is either an address or the size of the function
+                let size = match entry.attr_value(gimli::DW_AT_high_pc)? {
+                    Some(gimli::AttributeValue::Addr(high_pc)) => high_pc.saturating_sub(address),
+                    Some(high_pc) => match high_pc.udata_value() {
+                        Some(size) => size,
+                        None => continue,
+                    },
+                    None => continue,
+                };
+                let name = match Program::subprogram_name(dwarf, &unit, entry)? {
+                    Some(name) => name,
+                    None => continue,
+                };
+                if should_log_verbose() {
+                    log::trace!("Debug info function {} at {:#x}", name, address);
+                }
+                // Leaked since function names are static, like the mmapped
+                // file's symbol names
+                let name: &'static str = Box::leak(name.into_boxed_str());
+                let mut function = FunctionName::new(name, address);
+                function.in_symbol_table = false;
+                symbols.push(SymbolInfo {
+                    name: function,
+                    demangled_name: demangle::demangle(name, language),
+                    section_index: None,
+                    address,
+                    size,
+                    source_file: None,
+                });
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// Linkage (i.e. mangled) name of the subprogram `entry`, falling back to
+    /// its plain name. These may be in the declaration/abstract instance it
+    /// refers to.
+    fn subprogram_name(
+        dwarf: &gimli::Dwarf<gimli::EndianArcSlice<gimli::RunTimeEndian>>,
+        unit: &gimli::Unit<gimli::EndianArcSlice<gimli::RunTimeEndian>>,
+        entry: &gimli::DebuggingInformationEntry<gimli::EndianArcSlice<gimli::RunTimeEndian>>,
+    ) -> Result<Option<String>, gimli::Error> {
+        for attr in [
+            gimli::DW_AT_linkage_name,
+            gimli::DW_AT_MIPS_linkage_name,
+            gimli::DW_AT_name,
+        ] {
+            if let Some(value) = entry.attr_value(attr)? {
+                let name = dwarf.attr_string(unit, value)?;
+                return Ok(Some(name.to_string_lossy()?.into_owned()));
+            }
+        }
+        for attr in [gimli::DW_AT_specification, gimli::DW_AT_abstract_origin] {
+            if let Some(gimli::AttributeValue::UnitRef(offset)) = entry.attr_value(attr)? {
+                return Program::subprogram_name(dwarf, unit, &unit.entry(offset)?);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Mark functions defined with the same name as another function as
+    /// ambiguous, and look up their source file so they can be told apart.
+    fn mark_ambiguous_symbols(
+        symbols: &mut [SymbolInfo],
+        context: &addr2line::Context<gimli::EndianArcSlice<gimli::RunTimeEndian>>,
+    ) {
+        let mut name_counts: HashMap<&str, usize> = HashMap::new();
+        for symbol in symbols.iter().filter(|s| s.address != 0) {
+            *name_counts.entry(symbol.name.name).or_default() += 1;
+        }
+        for symbol in symbols.iter_mut() {
+            if symbol.address != 0 && name_counts[symbol.name.name] > 1 {
+                symbol.name.ambiguous = true;
+                symbol.source_file = match context.find_location(symbol.address) {
+                    Ok(Some(location)) => location.file.map(String::from),
+                    _ => None,
+                };
+                log::info!(
+                    "Function {} at {:#x} ({:?}) has an ambiguous name",
+                    symbol.name.name,
+                    symbol.address,
+                    symbol.source_file
+                );
+            }
+        }
+    }
+
+    /// Some distributions (e.g. Fedora) ship stripped binaries with an
+    /// xz-compressed ELF file containing the symbol table in the
+    /// `.gnu_debugdata` section, see
+    /// https://sourceware.org/gdb/onlinedocs/gdb/MiniDebugInfo.html.
+    ///
+    /// Returns None if the section is not present, else the parsed embedded
+    /// file/error.
+    fn get_mini_debug_info(file: &File<'static>) -> Option<Result<File<'static>, Error>> {
+        let compressed = match file.section_by_name(".gnu_debugdata")?.data() {
+            Ok(data) => data,
+            Err(err) => return Some(Err(format!("Failed to read section: {}", err).into())),
+        };
+        let data = match Program::xz_decompress(compressed) {
+            Ok(data) => data,
+            Err(err) => return Some(Err(err)),
+        };
+        // Leaked for the same reason as the mmap in `parse`
+        let data: &'static [u8] = Box::leak(data.into_boxed_slice());
+        Some(match object::File::parse(data) {
+            Ok(file) => Ok(file),
+            Err(err) => Err(format!("Failed to parse decompressed file: {}", err).into()),
+        })
+    }
+
+    fn xz_decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::new();
+        match lzma_rs::xz_decompress(&mut std::io::BufReader::new(data), &mut output) {
+            Ok(()) => Ok(output),
+            Err(err) => Err(format!("Failed to decompress: {}", err).into()),
+        }
+    }
+
     // `versioned_symbols_map` is a map from unversioned symbol name to the
     // versioned one. The dynamic symbols section seems to contain unversioned
     // symbol names.
-    fn dynamic_symbols_map(
+    fn got_symbols_map(
         file: &File<'static>,
         versioned_symbols_map: &HashMap<String, FunctionName>,
     ) -> HashMap<u64, FunctionName> {
@@ -235,38 +989,41 @@ impl Program {
                         if should_log_verbose() {
                             log::trace!("Relocation {:x} = {}", address, name);
                         }
+                        let name = if let Some(versioned_name) = versioned_symbols_map.get(name) {
+                            *versioned_name
+                        } else {
+                            // Undefined symbols have address 0, matching the
+                            // symbol table
+                            FunctionName::new(name, symbol.address())
+                        };
                         relocations.insert(address, name);
                     }
                 }
             }
         }
+        relocations
+    }
 
+    // Map from PLT entry address to the dynamic symbol it jumps to, through
+    // `got_symbols_map`.
+    fn dynamic_symbols_map(
+        file: &File<'static>,
+        arch: &dyn Architecture,
+        got_symbols_map: &HashMap<u64, FunctionName>,
+    ) -> HashMap<u64, FunctionName> {
         let mut map = HashMap::new();
-        let decoder = create_decoder();
         for section in file.sections() {
             if let (Ok(name), address) = (section.name(), section.address()) {
                 // Include .plt and .plt.got
                 if name.starts_with(".plt") {
                     let code = section.uncompressed_data().unwrap();
-                    for (instruction, ip) in
-                        get_instructions_with_mnemonic(&decoder, address, &code, Mnemonic::JMP)
-                    {
-                        assert!(instruction.operand_count > 0);
-                        let jump_address = instruction
-                            .calc_absolute_address(ip, &instruction.operands[0])
-                            .unwrap();
+                    for (ip, got_address) in arch.plt_entries(address, &code) {
                         if should_log_verbose() {
-                            log::trace!("PLT {:#x?} -> GOT {:#x?}", ip, jump_address);
+                            log::trace!("PLT {:#x?} -> GOT {:#x?}", ip, got_address);
                         }
                         // Ignore expected jumps to PLT0 - figure A-9 in
                         // https://refspecs.linuxfoundation.org/elf/elf.pdf
-                        if let Some(&name) = relocations.get(&jump_address) {
-                            let name = if let Some(versioned_name) = versioned_symbols_map.get(name)
-                            {
-                                *versioned_name
-                            } else {
-                                FunctionName(name)
-                            };
+                        if let Some(&name) = got_symbols_map.get(&got_address) {
                             map.insert(ip, name);
                         }
                     }
@@ -277,80 +1034,146 @@ impl Program {
         map
     }
 
-    // If .gnu_debuglink not found, returns None, else valid file/error
+    /// Search for a separate debug info file, in a similar manner to gdb
+    /// (https://sourceware.org/gdb/onlinedocs/gdb/Separate-Debug-Files.html):
+    /// first by build ID under `.build-id` in each debug directory, then by
+    /// `.gnu_debuglink` name in the program's directory (and its `.debug`
+    /// subdirectory), under each debug directory, and finally in the current
+    /// working directory. `debug_dirs` are searched before
+    /// `/usr/lib/debug`. Candidates are verified against the program's
+    /// build ID and debuglink CRC.
+    ///
+    /// Returns None if no candidate file exists, else the path and contents of
+    /// the first valid file or an error describing why the candidates are
+    /// invalid.
     fn get_debug_file(
         program_file: &File<'static>,
-        program_file_path: &String,
-    ) -> Option<Result<File<'static>, Error>> {
-        let debuglink_filename = match program_file.gnu_debuglink() {
-            Ok(link_opt) => match link_opt {
-                Some(link) => {
-                    let filename = std::str::from_utf8(link.0).unwrap().to_string();
-                    // TODO if file doesn't exist in cwd we should probably
-                    // check in original file_path's folder.
-                    let mut file = match std::fs::File::open(&filename) {
-                        Ok(file) => file,
-                        Err(err) => {
-                            return Some(Err(
-                                format!("Failed to open file {}: {}", filename, err).into()
-                            ))
-                        }
-                    };
-
-                    // Validate checksum
-                    let mut hash_fn = || {
-                        const READ_SIZE: usize = 1 << 20; // 1 MB
-                        let mut buf = vec![0; READ_SIZE];
-                        let mut hasher = crc32fast::Hasher::new();
-                        loop {
-                            match file.read(&mut buf) {
-                                Ok(0) => break,
-                                Ok(n) => hasher.update(&buf[0..n]),
-                                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
-                                Err(e) => {
-                                    return Err(Error::from(format!(
-                                        "Failed to read file {}: {}",
-                                        filename, e
-                                    )))
-                                }
-                            }
-                        }
-                        Ok(hasher.finalize())
-                    };
-
-                    match hash_fn() {
-                        Ok(hash) => {
-                            if hash != link.1 {
-                                log::info!(
-                                    "Expected hash {:x}, but actual debug file {} has hash {:x}",
-                                    link.1,
-                                    filename,
-                                    hash
-                                );
-                                return Some(Err(format!(
-                                    "Debug file {} does not correspond to {} (CRC mismatch)",
-                                    filename, program_file_path
-                                )
-                                .into()));
-                            }
-                        }
-                        Err(err) => return Some(Err(err)),
-                    }
-                    // TODO should we be checking BuildID too?
-                    filename
-                }
-                None => return None,
-            },
+        program_file_path: &str,
+        debug_dirs: &[String],
+    ) -> Option<Result<(String, File<'static>), Error>> {
+        let build_id = match program_file.build_id() {
+            Ok(build_id) => build_id,
+            Err(err) => return Some(Err(format!("Failed to get build ID: {}", err).into())),
+        };
+        let debuglink = match program_file.gnu_debuglink() {
+            Ok(link) => link,
             Err(err) => return Some(Err(format!("Failed to get .gnu_debuglink: {}", err).into())),
         };
-        let df = Program::parse(&debuglink_filename);
-        if df.is_ok() {
-            log::info!(
-                "Using debug file {} for address to line mappings",
-                debuglink_filename
-            );
+        let dirs: Vec<&str> = debug_dirs
+            .iter()
+            .map(|d| d.as_str())
+            .chain(iter::once(SYSTEM_DEBUG_DIR))
+            .collect();
+
+        let mut candidates = Vec::new();
+        if let Some(build_id) = build_id.filter(|id| id.len() >= 2) {
+            let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+            for dir in &dirs {
+                candidates.push(format!(
+                    "{}/.build-id/{}/{}.debug",
+                    dir,
+                    &hex[..2],
+                    &hex[2..]
+                ));
+            }
+        }
+        if let Some((filename, _)) = debuglink {
+            let filename = String::from_utf8_lossy(filename);
+            let program_dir = Path::new(program_file_path)
+                .parent()
+                .map(|d| d.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            candidates.push(format!("{}/{}", program_dir, filename));
+            candidates.push(format!("{}/.debug/{}", program_dir, filename));
+            for dir in &dirs {
+                candidates.push(format!("{}{}/{}", dir, program_dir, filename));
+            }
+            for dir in debug_dirs {
+                candidates.push(format!("{}/{}", dir, filename));
+            }
+            // Current working directory
+            candidates.push(filename.into_owned());
+        }
+
+        let mut errors = Vec::new();
+        for candidate in candidates.iter().filter(|c| Path::new(c).is_file()) {
+            match Program::check_debug_file(candidate, build_id, debuglink.map(|l| l.1)) {
+                Ok(df) => {
+                    log::info!(
+                        "Using debug file {} for address to line mappings",
+                        candidate
+                    );
+                    return Some(Ok((candidate.clone(), df)));
+                }
+                Err(err) => {
+                    log::info!("Ignoring debug file {}: {}", candidate, err);
+                    errors.push(err.to_string());
+                }
+            }
+        }
+        if errors.is_empty() {
+            log::info!("No debug file found, searched {:?}", candidates);
+            None
+        } else {
+            Some(Err(format!(
+                "Debug files do not correspond to {}:\n{}",
+                program_file_path,
+                errors.join("\n")
+            )
+            .into()))
         }
-        Some(df)
+    }
+
+    /// Check that the debug file at `path` matches the expected build ID and
+    /// debuglink CRC (if they are set), and parse it.
+    fn check_debug_file(
+        path: &str,
+        build_id: Option<&[u8]>,
+        crc: Option<u32>,
+    ) -> Result<File<'static>, Error> {
+        if let Some(expected_crc) = crc {
+            let mut file = match std::fs::File::open(path) {
+                Ok(file) => file,
+                Err(err) => return Err(format!("Failed to open file {}: {}", path, err).into()),
+            };
+            const READ_SIZE: usize = 1 << 20; // 1 MB
+            let mut buf = vec![0; READ_SIZE];
+            let mut hasher = crc32fast::Hasher::new();
+            loop {
+                match file.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => hasher.update(&buf[0..n]),
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(format!("Failed to read file {}: {}", path, e).into()),
+                }
+            }
+            let hash = hasher.finalize();
+            if hash != expected_crc {
+                return Err(format!(
+                    "{} has CRC {:x}, expected {:x} (CRC mismatch)",
+                    path, hash, expected_crc
+                )
+                .into());
+            }
+        }
+
+        let df = Program::parse(&path.to_string())?;
+        if let (Some(expected_id), Ok(Some(id))) = (build_id, df.build_id()) {
+            if id != expected_id {
+                return Err(
+                    format!("{} has a different build ID (build ID mismatch)", path).into(),
+                );
+            }
+        }
+        Ok(df)
+    }
+
+    pub fn arch(&self) -> &'static dyn Architecture {
+        self.arch
+    }
+
+    pub fn get_debug_dirs(&self) -> &[String] {
+        &self.debug_dirs
     }
 
     pub fn get_address(&self, function: FunctionName) -> u64 {
@@ -373,6 +1196,51 @@ impl Program {
         }
     }
 
+    /// Find the line in `source_file` that the code at `address` corresponds
+    /// to, using the outermost frame of its chain of inlined functions that is
+    /// in `source_file` - functions from `source_file` may themselves be
+    /// inlined, in which case we want the line they are called from rather
+    /// than a line in their body. Returns the line, along with the names of
+    /// the inlined functions the code is in within that frame (outermost
+    /// first, empty if it is directly in `source_file`).
+    pub fn get_inlined_location(
+        &self,
+        address: u64,
+        source_file: &str,
+    ) -> Option<(u32, Vec<String>)> {
+        let frames: Vec<_> = match self.context.find_frames(address) {
+            Ok(frames) => frames.collect().ok()?,
+            Err(_) => return None,
+        };
+        // Frames are ordered innermost first
+        let index = frames.iter().rposition(|frame| match &frame.location {
+            Some(location) => location.file == Some(source_file) && location.line.is_some(),
+            None => false,
+        })?;
+        let line = frames[index].location.as_ref().unwrap().line.unwrap();
+        let inline_chain = frames[..index]
+            .iter()
+            .rev()
+            .map(|frame| match &frame.function {
+                Some(function) => match function.raw_name() {
+                    Ok(name) => demangle::demangle(&name, function.language)
+                        .unwrap_or_else(|| name.into_owned()),
+                    Err(_) => String::from("(unknown)"),
+                },
+                None => String::from("(unknown)"),
+            })
+            .collect();
+        Some((line, inline_chain))
+    }
+
+    /// Compilation directory (DW_AT_comp_dir) of the compilation unit
+    /// containing `address`
+    pub fn get_comp_dir(&self, address: u64) -> Option<String> {
+        let unit = self.context.find_dwarf_unit(address)?;
+        let comp_dir = unit.comp_dir.as_ref()?;
+        comp_dir.to_string_lossy().ok().map(|d| d.into_owned())
+    }
+
     #[allow(dead_code)]
     fn print_frames(&self, address: u64) {
         log::info!(
@@ -389,7 +1257,7 @@ impl Program {
     }
 
     // Returns (address, data) for given function
-    pub fn get_data(&self, function: FunctionName) -> Result<(u64, &[u8]), Error> {
+    pub fn get_data(&self, function: FunctionName) -> Result<(u64, &'static [u8]), Error> {
         let symbol = &self.name_to_symbol.get(&function).unwrap();
         let address = symbol.address;
         if address == 0 {
@@ -398,25 +1266,363 @@ impl Program {
             );
         }
         let size = symbol.size;
-        let index = symbol.section_index.unwrap();
-        Ok((
-            address,
-            self.file
-                .section_by_index(index)
-                .unwrap()
-                .data_range(address, size)
-                .unwrap()
-                .unwrap(),
-        ))
+        let section = match symbol.section_index {
+            Some(index) => self.file.section_by_index(index).unwrap(),
+            // Symbols from MiniDebugInfo don't have a valid section index
+            None => match self.get_section_containing(address) {
+                Some(section) => section,
+                None => {
+                    return Err(
+                        format!("Failed to find section containing symbol {}", function).into(),
+                    )
+                }
+            },
+        };
+        match section.data_range(address, size) {
+            Ok(Some(data)) => Ok((address, data)),
+            _ => Err(format!("Failed to get data for symbol {}", function).into()),
+        }
+    }
+
+    /// Returns (caller, call instruction address) of all direct calls (and
+    /// tail jumps) to `function` in this program, or None if the callers
+    /// index hasn't been built yet (see `callers_indexer`).
+    pub fn get_callers(&self, function: FunctionName) -> Option<Vec<(FunctionName, u64)>> {
+        let address = self.get_address(function);
+        let callers = self.callers.as_ref()?;
+        Some(callers.get(&address).cloned().unwrap_or_default())
+    }
+
+    /// Returns None if the callers index is available, reading it from the
+    /// cache if possible. Otherwise returns an indexer to build it with (e.g.
+    /// on another thread), which should be passed to `set_callers_index`.
+    pub fn callers_indexer(&mut self) -> Option<CallersIndexer> {
+        if self.callers.is_some() {
+            return None;
+        }
+        let cached = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.read::<CachedCallersIndex>(CALLERS_CACHE));
+        if let Some(cached) = cached {
+            let callers = cached
+                .into_iter()
+                .map(|(address, callers)| {
+                    let callers = callers
+                        .into_iter()
+                        .filter_map(|(caller, ip)| {
+                            self.address_to_name.get(&caller).map(|&f| (f, ip))
+                        })
+                        .collect();
+                    (address, callers)
+                })
+                .collect();
+            self.callers = Some(callers);
+            return None;
+        }
+
+        let mut name_to_functions: HashMap<&str, Vec<FunctionName>> = HashMap::new();
+        for &function in self.address_to_name.values() {
+            name_to_functions
+                .entry(function.name)
+                .or_default()
+                .push(function);
+        }
+        let mut functions = Vec::new();
+        let mut function_starts = HashMap::new();
+        for (&address, &part) in &self.address_to_name {
+            if self.is_dynamic_symbol_address(address) {
+                continue;
+            }
+            // Attribute calls in separate parts of functions (see
+            // `get_function_parts`) to the function itself
+            let function = cold_part_base(part.name)
+                .and_then(|name| name_to_functions.get(name))
+                .and_then(|functions| {
+                    functions
+                        .iter()
+                        .copied()
+                        .find(|&f| !f.ambiguous || self.in_same_unit(f.address, address))
+                })
+                .unwrap_or(part);
+            function_starts.insert(address, function);
+            match self.get_data(part) {
+                Ok((_, code)) => functions.push((function, address, code)),
+                Err(err) => log::trace!("Skipping {}: {}", part, err),
+            }
+        }
+        Some(CallersIndexer {
+            arch: self.arch,
+            functions,
+            function_starts,
+        })
+    }
+
+    /// Set the callers index built by `callers_indexer`
+    pub fn set_callers_index(&mut self, callers: CallersIndex) {
+        if let Some(cache) = &self.cache {
+            let cached: CachedCallersIndex = callers
+                .iter()
+                .map(|(&address, callers)| {
+                    let callers = callers
+                        .iter()
+                        .map(|(caller, ip)| (caller.address, *ip))
+                        .collect();
+                    (address, callers)
+                })
+                .collect();
+            cache.write(CALLERS_CACHE, &cached);
+        }
+        self.callers = Some(callers);
+    }
+
+    fn get_section_containing(&self, address: u64) -> Option<object::Section<'static, '_>> {
+        self.file
+            .sections()
+            .find(|s| s.address() <= address && address < s.address() + s.size())
+    }
+
+    /// Functions at the given (byte) offset from the address point of any C++
+    /// vtable in the program, i.e. the possible targets of a virtual call
+    /// through `[vtable pointer + displacement]`. Besides the primary vtable,
+    /// a vtable symbol contains a secondary vtable for each base class that
+    /// doesn't share the object's address (multiple and virtual
+    /// inheritance), which are included too.
+    pub fn get_vtable_candidates(&self, displacement: u64) -> Vec<FunctionName> {
+        const ENTRY_SIZE: u64 = 8;
+        let mut vtables = self.vtables.clone();
+        vtables.sort_unstable();
+        let in_vtable = |address: u64| {
+            let index = vtables.partition_point(|&(start, _)| start <= address);
+            index > 0 && {
+                let (start, size) = vtables[index - 1];
+                address < start + size
+            }
+        };
+        // In position independent binaries vtable entries are filled in by
+        // dynamic relocations
+        let relocations: HashMap<u64, object::Relocation> = self
+            .file
+            .dynamic_relocations()
+            .map(|relocations| {
+                relocations
+                    .filter(|(address, _)| in_vtable(*address))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let entry = |address: u64| -> Option<VtableEntry> {
+            if let Some(relocation) = relocations.get(&address) {
+                return match relocation.target() {
+                    object::RelocationTarget::Absolute => {
+                        Some(VtableEntry::Address(relocation.addend() as u64))
+                    }
+                    object::RelocationTarget::Symbol(index) => Some(VtableEntry::Symbol(index)),
+                    _ => None,
+                };
+            }
+            let data = self
+                .get_section_containing(address)
+                .and_then(|section| section.data_range(address, ENTRY_SIZE).ok().flatten())?;
+            Some(VtableEntry::Address(u64::from_le_bytes(
+                data.try_into().unwrap(),
+            )))
+        };
+
+        let entry_function = |entry: &VtableEntry| match *entry {
+            VtableEntry::Address(address) => self.get_function_for_address(address),
+            VtableEntry::Symbol(index) => self
+                .file
+                .dynamic_symbol_table()
+                .and_then(|symbols| symbols.symbol_by_index(index).ok())
+                .and_then(|symbol| symbol.name().ok())
+                .and_then(|name| self.find_function(name)),
+        };
+        // Whether the entry at `address` is a pointer rather than an offset.
+        // Pointers are always relocated in position independent binaries.
+        let is_pointer = |address: u64, entry: &VtableEntry| match *entry {
+            VtableEntry::Symbol(_) => true,
+            VtableEntry::Address(value) => {
+                relocations.contains_key(&address)
+                    || (self.file.kind() == object::ObjectKind::Executable
+                        && self.file.segments().any(|segment| {
+                            segment.address() <= value && value < segment.address() + segment.size()
+                        }))
+            }
+        };
+
+        let mut functions = HashSet::new();
+        for &(start, size) in &vtables {
+            let end = start + size;
+            let entries: Vec<(u64, Option<VtableEntry>)> = (start..end)
+                .step_by(ENTRY_SIZE as usize)
+                .map(|address| (address, entry(address)))
+                .collect();
+            // Each vtable in the group starts with (virtual base and call)
+            // offsets, offset-to-top and the typeinfo of the complete object,
+            // which is followed by its address point (which objects point
+            // to). The typeinfo is the first pointer, unless the program was
+            // built without RTTI, in which case only the primary vtable
+            // (assumed to have no virtual bases) is found.
+            let typeinfo = entries
+                .iter()
+                .find_map(|(address, entry)| match entry {
+                    Some(entry) if is_pointer(*address, entry) => Some(entry),
+                    _ => None,
+                })
+                .filter(|entry| entry_function(entry).is_none());
+            let address_points: Vec<u64> = match typeinfo {
+                Some(typeinfo) => entries
+                    .iter()
+                    .filter(|(_, entry)| entry.as_ref() == Some(typeinfo))
+                    .map(|(address, _)| address + ENTRY_SIZE)
+                    .collect(),
+                None => vec![start + 2 * ENTRY_SIZE],
+            };
+            for (i, &address_point) in address_points.iter().enumerate() {
+                // Entries of a vtable end before the offset-to-top of the
+                // next one
+                let vtable_end = address_points
+                    .get(i + 1)
+                    .map_or(end, |next| next - 2 * ENTRY_SIZE);
+                let slot = address_point + displacement;
+                if slot + ENTRY_SIZE > vtable_end {
+                    continue;
+                }
+                let index = ((slot - start) / ENTRY_SIZE) as usize;
+                functions.extend(entries[index].1.as_ref().and_then(entry_function));
+            }
+        }
+
+        let mut functions: Vec<FunctionName> = functions
+            .into_iter()
+            .filter(|f| f.name != "__cxa_pure_virtual")
+            .collect();
+        functions.sort_by_key(|f| (f.name, f.address));
+        functions
     }
 
     pub fn get_symbol(&self, function: FunctionName) -> Option<&SymbolInfo> {
         self.name_to_symbol.get(&function)
     }
 
+    /// The function in this program with the same name and address as
+    /// `function`, which may be from a partially loaded program (see
+    /// `LoadProgress::Symbols`).
+    pub fn resolve_function(&self, function: FunctionName) -> Option<FunctionName> {
+        self.name_to_symbol
+            .keys()
+            .find(|f| f.name == function.name && f.address == function.address)
+            .copied()
+    }
+
+    /// Returns None if the line index is available. Otherwise returns an
+    /// indexer to build it with (e.g. on another thread), which should be
+    /// passed to `set_line_index`.
+    pub fn line_indexer(&self) -> Option<LineIndexer> {
+        if self.line_index.is_some() {
+            return None;
+        }
+        Some(LineIndexer {
+            file_path: self
+                .debug_file_path
+                .clone()
+                .unwrap_or_else(|| self.file_path.clone()),
+            functions: self
+                .name_to_symbol
+                .values()
+                .map(|s| (s.address, s.size, s.name))
+                .collect(),
+        })
+    }
+
+    pub fn set_line_index(&mut self, line_index: LineIndex) {
+        self.line_index = Some(Arc::new(line_index));
+    }
+
+    /// All source files in the program's line tables, sorted, or None if the
+    /// line tables haven't been indexed yet
+    pub fn source_files(&self) -> Option<Vec<String>> {
+        self.line_index.as_ref().map(|index| index.files())
+    }
+
+    /// (first line, function) of each function starting in source file
+    /// `file`, sorted by line
+    pub fn function_lines(&self, file: &str) -> Vec<(u32, FunctionName)> {
+        self.line_index
+            .as_ref()
+            .map_or_else(Vec::new, |index| index.function_lines(file))
+    }
+
+    /// Find a function defined in this program by name, ignoring any symbol
+    /// version suffix (e.g. `@@GLIBC_2.2.5`).
+    pub fn find_function(&self, name: &str) -> Option<FunctionName> {
+        self.name_to_symbol
+            .values()
+            .filter(|s| s.address != 0 && s.name.name.split('@').next().unwrap() == name)
+            // Prefer an unversioned name
+            .min_by_key(|s| (s.name.name.len(), s.name.ambiguous))
+            .map(|s| s.name)
+    }
+
+    /// Parts of `function` that the compiler split off into separate symbols,
+    /// i.e. `foo.cold` (or `foo.cold.N`) symbols containing unlikely code,
+    /// generated by GCC's `-freorder-blocks-and-partition`.
+    /// Parts are found by symbol name rather than through the function's
+    /// `DW_AT_ranges`, since they can only be probed through their symbols.
+    pub fn get_function_parts(&self, function: FunctionName) -> Vec<FunctionName> {
+        let parts = match self.function_parts.get(function.name) {
+            Some(parts) => parts,
+            None => return Vec::new(),
+        };
+        parts
+            .iter()
+            // Parts of local functions with the same name can only be told
+            // apart by their compilation unit
+            .filter(|f| !function.ambiguous || self.in_same_unit(function.address, f.address))
+            .copied()
+            .collect()
+    }
+
+    fn in_same_unit(&self, address1: u64, address2: u64) -> bool {
+        match (
+            self.context.find_dwarf_unit(address1),
+            self.context.find_dwarf_unit(address2),
+        ) {
+            (Some(unit1), Some(unit2)) => std::ptr::eq(unit1, unit2),
+            _ => false,
+        }
+    }
+
+    /// Find functions defined in this program whose demangled name matches
+    /// `pattern`, in which `*` matches any sequence of characters. Unless the
+    /// pattern contains a parameter list, names are matched without theirs so
+    /// that all overloads match, e.g. `Cache<*>::lookup`. Any return type
+    /// (present in demangled template functions) is ignored.
+    pub fn find_functions_matching(&self, pattern: &str) -> Vec<FunctionName> {
+        let match_parameters = pattern.contains('(');
+        let mut functions: Vec<FunctionName> = self
+            .name_to_symbol
+            .values()
+            .filter(|s| s.address != 0)
+            .filter(|s| {
+                let name = if match_parameters {
+                    s.as_ref()
+                } else {
+                    strip_parameters(s.as_ref())
+                };
+                glob_match(pattern, name) || glob_match(pattern, strip_return_type(name))
+            })
+            .map(|s| s.name)
+            .collect();
+        functions.sort_by_key(|f| (f.name, f.address));
+        functions
+    }
+
     pub fn symbols_generator(&self) -> SymbolsGenerator {
         SymbolsGenerator {
             name_to_symbol: Arc::clone(&self.name_to_symbol),
+            line_index: self.line_index.clone(),
+            dynamic_symbols_ranges: Arc::clone(&self.dynamic_symbols_ranges),
         }
     }
 
@@ -428,61 +1634,117 @@ impl Program {
         }
     }
 
+    /// Function that the GOT entry at `address` is relocated to, if any
+    pub fn get_function_for_got_entry(&self, address: u64) -> Option<FunctionName> {
+        self.got_symbols_map.get(&address).copied()
+    }
+
     pub fn is_dynamic_symbol_address(&self, address: u64) -> bool {
         self.dynamic_symbols_ranges
             .iter()
             .any(|r| r.contains(&address))
     }
 
+    /// Whether this is a function defined in a shared library, i.e. it is
+    /// undefined in this program or is a PLT entry
     pub fn is_dynamic_symbol(&self, symbol: &SymbolInfo) -> bool {
-        self.is_dynamic_symbol_address(symbol.address)
+        symbol.address == 0 || self.is_dynamic_symbol_address(symbol.address)
     }
 }
 
-pub fn create_decoder() -> Decoder {
-    // TODO make platform independent
-    Decoder::new(MachineMode::LONG_64, AddressWidth::_64).unwrap()
+/// If `name` is the symbol of a `.cold` part of a function, the name of the
+/// function
+fn cold_part_base(name: &str) -> Option<&str> {
+    let i = name.rfind(".cold")?;
+    match &name[i + ".cold".len()..] {
+        "" => Some(&name[..i]),
+        suffix => match suffix.strip_prefix('.') {
+            Some(n) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => Some(&name[..i]),
+            _ => None,
+        },
+    }
 }
 
-pub fn get_instructions_with_mnemonic<'a, 'b>(
-    decoder: &'a Decoder,
-    start_address: u64,
-    code: &'b [u8],
-    mnemonic: Mnemonic,
-) -> CallIterator<'a, 'b> {
-    CallIterator {
-        it: decoder.instruction_iterator(code, start_address),
-        mnemonic,
+/// Remove the trailing parameter list (and any qualifiers following it) from a
+/// demangled function name.
+pub fn strip_parameters(name: &str) -> &str {
+    let close = match name.rfind(')') {
+        Some(close) => close,
+        None => return name,
+    };
+    let mut depth = 0;
+    for (i, c) in name[..=close].char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' => {
+                depth -= 1;
+                if depth == 0 {
+                    return &name[..i];
+                }
+            }
+            _ => (),
+        }
     }
+    name
 }
 
-pub struct CallIterator<'a, 'b> {
-    it: zydis::InstructionIterator<'a, 'b>,
-    mnemonic: Mnemonic,
+/// Remove the return type from a demangled function name, i.e. everything up
+/// to the last space that is not within template arguments or parentheses.
+pub fn strip_return_type(name: &str) -> &str {
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in name.char_indices() {
+        match c {
+            '<' | '(' => depth += 1,
+            '>' | ')' => depth -= 1,
+            ' ' if depth == 0 => start = i + 1,
+            _ => (),
+        }
+    }
+    &name[start..]
 }
 
-impl Iterator for CallIterator<'_, '_> {
-    type Item = (DecodedInstruction, u64);
-
-    fn next(&mut self) -> Option<(DecodedInstruction, u64)> {
-        while let Some((instruction, ip)) = self.it.next() {
-            if instruction.mnemonic == self.mnemonic {
-                if log::log_enabled!(log::Level::Trace) {
-                    let formatter = Formatter::new(FormatterStyle::INTEL)
-                        .expect("Could not create zydis Formatter");
-                    let mut buffer = [0u8; 200];
-                    let mut buffer = OutputBuffer::new(&mut buffer[..]);
-                    formatter
-                        .format_instruction(&instruction, &mut buffer, Some(ip), None)
-                        .unwrap();
-                    log::trace!("{} 0x{:016X} {}", instruction.operand_count, ip, buffer);
-                }
+/// Remove all template arguments from a demangled name, e.g.
+/// `Cache<int>::lookup<long>` becomes `Cache::lookup`.
+pub fn strip_template_arguments(name: &str) -> String {
+    let mut stripped = String::with_capacity(name.len());
+    let mut depth = 0;
+    for c in name.chars() {
+        match c {
+            '<' => depth += 1,
+            '>' if depth > 0 => depth -= 1,
+            _ if depth == 0 => stripped.push(c),
+            _ => (),
+        }
+    }
+    stripped
+}
 
-                return Some((instruction, ip));
-            }
+/// Whether `text` matches `pattern`, in which `*` matches any sequence of
+/// characters
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` in pattern, and of text when it was reached
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // Let the last `*` match one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
         }
-        None
     }
+    pattern[p..].iter().all(|&c| c == b'*')
 }
 
 /// Clone (plus inlining) of addr2line::ObjectContext::new, just using Arc
@@ -515,3 +1777,333 @@ pub fn new_context<'data: 'file, 'file, O: object::Object<'data, 'file>>(
     let dwarf = gimli::Dwarf::load(|id| load_section(id, file, endian))?;
     addr2line::Context::from_dwarf(dwarf)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_function_pattern() {
+        assert_eq!(strip_parameters("Foo::bar(int) const"), "Foo::bar");
+        assert_eq!(
+            strip_parameters("(anonymous namespace)::parse(char const*)"),
+            "(anonymous namespace)::parse"
+        );
+        assert_eq!(strip_parameters("Foo::operator()(int)"), "Foo::operator()");
+        assert_eq!(strip_parameters("main"), "main");
+        assert_eq!(
+            strip_return_type("std::pair<int, int> make<int, int>"),
+            "make<int, int>"
+        );
+        assert_eq!(
+            strip_template_arguments("Cache<std::pair<int, int> >::lookup<long>"),
+            "Cache::lookup"
+        );
+
+        assert!(glob_match("Cache<*>::lookup", "Cache<std::string>::lookup"));
+        assert!(glob_match("*::lookup", "a::b::lookup"));
+        assert!(glob_match("Foo::bar", "Foo::bar"));
+        assert!(!glob_match("Foo::bar", "Foo::bar2"));
+        assert!(!glob_match("Cache<*>::lookup", "Cache<int>::lookup_all"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+    }
+
+    /// Loads fixture `name`, see `testdata/build.sh`
+    fn load_fixture(name: &str) -> Program {
+        crate::cache::use_test_cache_home();
+        let path = format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), name);
+        Program::new(path, Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn test_load_progress() {
+        crate::cache::use_test_cache_home();
+        let path = format!("{}/testdata/tailcall", env!("CARGO_MANIFEST_DIR"));
+        // The second load uses cached symbols
+        for _ in 0..2 {
+            let mut events = Vec::new();
+            Program::load(path.clone(), Vec::new(), |progress| {
+                events.push(match progress {
+                    LoadProgress::Stage(stage) => stage,
+                    LoadProgress::Symbols(_) => "Symbols",
+                })
+            })
+            .unwrap();
+            let position = |event| events.iter().position(|e| *e == event).unwrap();
+            // Symbols are searchable before debug info is read
+            assert!(position("Symbols") < position("Reading debug info"));
+        }
+    }
+
+    #[test]
+    fn test_line_indexer() {
+        crate::cache::use_test_cache_home();
+        let path = format!("{}/testdata/inline", env!("CARGO_MANIFEST_DIR"));
+        let mut program = Program::load(path, Vec::new(), |_| ()).unwrap();
+        assert!(program.source_files().is_none());
+        let indexer = program.line_indexer().unwrap();
+        program.set_line_index(indexer.run());
+        assert!(program.line_indexer().is_none());
+        let source_files = program.source_files().unwrap();
+        assert!(source_files.iter().any(|f| f.ends_with("inline.c")));
+    }
+
+    #[test]
+    fn test_mini_debug_info() {
+        let program = load_fixture("minidebuginfo");
+        let mini_debug_info = Program::get_mini_debug_info(&program.file)
+            .unwrap()
+            .unwrap();
+        let names: Vec<&str> = Program::function_symbols(&mini_debug_info)
+            .iter()
+            .map(|s| s.name.name)
+            .collect();
+        assert!(names.contains(&"mini_debug_info_function"));
+        assert!(names.contains(&"main"));
+        // The program itself has no symbol table
+        assert!(Program::function_symbols(&program.file).is_empty());
+
+        let function = program.find_function("mini_debug_info_function").unwrap();
+        let symbol = program.get_symbol(function).unwrap();
+        assert!(symbol.section_index.is_none());
+        assert!(program.get_data(function).is_ok());
+
+        assert!(Program::xz_decompress(b"not xz").is_err());
+    }
+
+    #[test]
+    fn test_get_inlined_location() {
+        let program = load_fixture("inline");
+        let outer = program.find_function("outer").unwrap();
+        let leaf_address = program.get_address(program.find_function("leaf").unwrap());
+        let (address, code) = program.get_data(outer).unwrap();
+        let call = program
+            .arch()
+            .calls(address, code)
+            .into_iter()
+            .find(|call| call.target == BranchTarget::Address(leaf_address))
+            .unwrap();
+        let source_file = program.get_location(address).unwrap().file.unwrap();
+        assert!(source_file.ends_with("inline.c"));
+        // `leaf` is called on line 6 of `helper`, which is inlined into
+        // `outer` on line 10
+        assert_eq!(
+            program.get_inlined_location(call.address, source_file),
+            Some((10, vec!["helper".to_string()]))
+        );
+        assert_eq!(program.get_inlined_location(call.address, "other.c"), None);
+    }
+
+    #[test]
+    fn test_get_vtable_candidates() {
+        for fixture in &["vtables", "vtables_no_pie"] {
+            let program = load_fixture(fixture);
+            let candidates = |displacement| -> Vec<&str> {
+                program
+                    .get_vtable_candidates(displacement)
+                    .iter()
+                    .map(|f| f.name)
+                    .collect()
+            };
+            // The second slot of B's vtable, which is also in the secondary
+            // vtable for B in C. D's primary vtable starts with a virtual
+            // base offset.
+            assert_eq!(
+                candidates(8),
+                vec!["_ZN1B2b2Ev", "_ZN1C2b2Ev", "_ZN1D1vEv", "_ZThn8_N1C2b2Ev"],
+                "{}",
+                fixture
+            );
+            // Includes the secondary vtable for the virtual base V in D,
+            // which starts with a virtual call offset
+            assert_eq!(
+                candidates(0),
+                vec![
+                    "_ZN1A1aEv",
+                    "_ZN1B2b1Ev",
+                    "_ZN1C1aEv",
+                    "_ZN1V1vEv",
+                    "_ZTv0_n24_N1D1vEv"
+                ],
+                "{}",
+                fixture
+            );
+            assert!(candidates(16).is_empty(), "{}", fixture);
+        }
+    }
+
+    #[test]
+    fn test_callers_index() {
+        let mut program = load_fixture("tailcall");
+        if let Some(indexer) = program.callers_indexer() {
+            let mut percent = 0;
+            let callers = indexer.run(|p| percent = p);
+            assert_eq!(percent, 100);
+            program.set_callers_index(callers);
+        }
+        let callers = |name| -> Vec<&str> {
+            let function = program.find_function(name).unwrap();
+            program
+                .get_callers(function)
+                .unwrap()
+                .iter()
+                .map(|(caller, _)| caller.name)
+                .collect()
+        };
+        assert_eq!(callers("leaf"), vec!["caller", "tail"]);
+        assert_eq!(callers("tail"), vec!["main"]);
+    }
+
+    #[test]
+    fn test_function_parts() {
+        let mut program = load_fixture("cold");
+        let check = program.find_function("check").unwrap();
+        let parts = program.get_function_parts(check);
+        let names: Vec<&str> = parts.iter().map(|f| f.name).collect();
+        assert_eq!(names, vec!["check.cold"]);
+        let report = program.find_function("report").unwrap();
+        assert!(program.get_function_parts(report).is_empty());
+
+        let (address, code) = program.get_data(parts[0]).unwrap();
+        let calls = program.arch().calls(address, code);
+        assert_eq!(calls.len(), 2);
+        assert!(calls
+            .iter()
+            .all(|call| call.target == BranchTarget::Address(report.address)));
+        // Calls in the part are attributed to the function
+        if let Some(indexer) = program.callers_indexer() {
+            program.set_callers_index(indexer.run(|_| ()));
+        }
+        let callers = program.get_callers(report).unwrap();
+        assert_eq!(callers.len(), 2);
+        assert!(callers.iter().all(|&(caller, _)| caller == check));
+    }
+
+    #[test]
+    fn test_mark_ambiguous_symbols() {
+        let program = load_fixture("ambiguous");
+        let mut helpers: Vec<u64> = program
+            .address_to_name
+            .iter()
+            .filter(|(_, f)| f.name == "helper")
+            .map(|(&address, _)| address)
+            .collect();
+        helpers.sort_unstable();
+        assert_eq!(helpers.len(), 2);
+        assert!(program.find_function("helper").unwrap().is_ambiguous());
+        assert!(!program.find_function("main").unwrap().is_ambiguous());
+
+        let symbol = SymbolInfo::new;
+        let main = program.find_function("main").unwrap().address;
+        // Undefined symbols (address 0) don't make a name ambiguous
+        let mut symbols = vec![
+            symbol("helper", helpers[0]),
+            symbol("helper", helpers[1]),
+            symbol("helper", 0),
+            symbol("main", main),
+            symbol("main", 0),
+        ];
+        Program::mark_ambiguous_symbols(&mut symbols, &program.context);
+        let marked: Vec<(bool, Option<&str>)> = symbols
+            .iter()
+            .map(|s| {
+                let file = s.source_file.as_deref();
+                (
+                    s.name.is_ambiguous(),
+                    file.map(|f| f.rsplit('/').next().unwrap()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            marked,
+            vec![
+                (true, Some("ambiguous_a.c")),
+                (true, Some("ambiguous_b.c")),
+                (false, None),
+                (false, None),
+                (false, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_probe_target() {
+        let function = FunctionName::new("foo", 0x1000);
+        assert_eq!(function.probe_target(0), "foo");
+        assert_eq!(function.probe_target(16), "foo+16");
+        // bpftrace would pick any of the functions with the name
+        let ambiguous = FunctionName {
+            ambiguous: true,
+            ..function
+        };
+        assert_eq!(ambiguous.probe_target(0), "0x1000");
+        assert_eq!(ambiguous.probe_target(16), "0x1010");
+        // bpftrace can only look up names in the symbol table
+        let debug_info_only = FunctionName {
+            in_symbol_table: false,
+            ..function
+        };
+        assert_eq!(debug_info_only.probe_target(0), "0x1000");
+        assert_eq!(debug_info_only.probe_target(16), "0x1010");
+    }
+
+    #[test]
+    fn test_debug_info_symbols() {
+        let program = load_fixture("debuginfo_only");
+        let hidden = program.find_function("hidden").unwrap();
+        let visible = program.find_function("visible").unwrap();
+        assert!(!hidden.is_in_symbol_table());
+        assert!(visible.is_in_symbol_table());
+        assert_eq!(hidden.probe_target(4), format!("{:#x}", hidden.address + 4));
+        assert_eq!(visible.probe_target(4), "visible+4");
+
+        let symbol_addresses: HashSet<u64> = iter::once(visible.address).collect();
+        let symbols = Program::debug_info_symbols(&program.context, &symbol_addresses).unwrap();
+        let mut names: Vec<&str> = symbols.iter().map(|s| s.name.name).collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["hidden", "main"]);
+        let symbol = symbols.iter().find(|s| s.name == hidden).unwrap();
+        assert!(symbol.size > 0);
+        assert_eq!(symbol.section_index, None);
+    }
+
+    #[test]
+    fn test_aarch64() {
+        let program = load_fixture("aarch64");
+        assert_eq!(program.arch().bpftrace_ip_register(), "pc");
+        let start = program.find_function("_start").unwrap();
+        let local_func = program.find_function("local_func").unwrap();
+        let (address, code) = program.get_data(start).unwrap();
+
+        let calls = program.arch().calls(address, code);
+        let targets: Vec<&BranchTarget> = calls.iter().map(|call| &call.target).collect();
+        assert_eq!(targets.len(), 3);
+        assert_eq!(targets[0], &BranchTarget::Address(local_func.address));
+        // Calls to the shared library go through the PLT
+        let plt_entry = match targets[1] {
+            BranchTarget::Address(address) => *address,
+            target => panic!("Unexpected call target {:?}", target),
+        };
+        assert!(program.is_dynamic_symbol_address(plt_entry));
+        let ext_func = program.get_function_for_address(plt_entry).unwrap();
+        assert_eq!(ext_func.name, "ext_func");
+        assert_eq!(targets[2], &BranchTarget::Register("r8".to_string()));
+
+        let jumps = program.arch().jumps(address, code);
+        assert_eq!(jumps.len(), 1);
+        assert_eq!(jumps[0].target, BranchTarget::Address(plt_entry));
+        assert_eq!(
+            program.get_location(jumps[0].address).unwrap().line,
+            Some(20)
+        );
+    }
+
+    #[test]
+    fn test_cold_part_base() {
+        assert_eq!(cold_part_base("_Z3foov.cold"), Some("_Z3foov"));
+        assert_eq!(cold_part_base("foo.cold.12"), Some("foo"));
+        assert_eq!(cold_part_base("foo.cold."), None);
+        assert_eq!(cold_part_base("foo.colder"), None);
+        assert_eq!(cold_part_base("foo.part.0"), None);
+    }
+}
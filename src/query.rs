@@ -0,0 +1,263 @@
+use crate::error::Error;
+use crate::symbol_tree;
+use regex::Regex;
+
+/// Properties of a function that query filters are evaluated against
+pub trait Fields {
+    /// Demangled name
+    fn name(&self) -> &str;
+    fn source_file(&self) -> Option<&str>;
+    fn size(&self) -> u64;
+    /// Whether the function is defined in a shared library
+    fn is_dynamic(&self) -> bool;
+}
+
+#[derive(Debug, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+#[derive(Debug)]
+enum Filter {
+    /// `/regex/`, matched against the name
+    Regex(Regex),
+    /// `file:PATH`, source file path contains PATH
+    File(String),
+    /// `size:>N` etc., size in bytes
+    Size(Comparison, u64),
+    /// `ns:NAMESPACE`, function is in a namespace or class with this
+    /// (possibly nested) name
+    Namespace(String),
+    /// `is:dyn`, function is defined in a shared library
+    Dynamic,
+}
+
+impl Filter {
+    fn matches(&self, fields: &dyn Fields) -> bool {
+        match self {
+            Filter::Regex(regex) => regex.is_match(fields.name()),
+            Filter::File(path) => {
+                matches!(fields.source_file(), Some(file) if file.contains(path.as_str()))
+            }
+            Filter::Size(comparison, size) => {
+                let actual = fields.size();
+                match comparison {
+                    Comparison::Less => actual < *size,
+                    Comparison::LessOrEqual => actual <= *size,
+                    Comparison::Equal => actual == *size,
+                    Comparison::GreaterOrEqual => actual >= *size,
+                    Comparison::Greater => actual > *size,
+                }
+            }
+            Filter::Namespace(namespace) => {
+                let scopes = symbol_tree::split_scopes(fields.name());
+                let scopes = format!("::{}::", scopes[..scopes.len() - 1].join("::"));
+                scopes.contains(&format!("::{}::", namespace))
+            }
+            Filter::Dynamic => fields.is_dynamic(),
+        }
+    }
+}
+
+/// A search with filters, see `parse`
+#[derive(Debug)]
+pub struct Query {
+    /// Remaining search text, for fuzzy (or exact, if prefixed with `=`)
+    /// matching
+    pub text: String,
+    /// (negated, filter)
+    filters: Vec<(bool, Filter)>,
+}
+
+impl Query {
+    /// Whether `fields` passes all filters
+    pub fn matches(&self, fields: &dyn Fields) -> bool {
+        self.filters
+            .iter()
+            .all(|(negated, filter)| filter.matches(fields) != *negated)
+    }
+
+    pub fn has_filters(&self) -> bool {
+        !self.filters.is_empty()
+    }
+}
+
+/// Parse a search into filters and the remaining search text. Filters are
+/// whitespace separated terms of the form `/regex/`, `file:PATH`,
+/// `size:>N` (or `<`, `<=`, `>=`, or `=` by default), `ns:NAMESPACE` or
+/// `is:dyn`, and can be negated by prefixing them with `-`. All filters must
+/// match. Other terms are searched for, e.g. `dyn` in Rust trait object
+/// types.
+pub fn parse(search: &str) -> Result<Query, Error> {
+    let mut text = Vec::new();
+    let mut filters = Vec::new();
+    let mut terms = search.split_whitespace();
+    while let Some(term) = terms.next() {
+        let (negated, filter_term) = match term.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, term),
+        };
+        let filter = if let Some(pattern) = filter_term.strip_prefix('/') {
+            // Regexes may contain spaces, so consume terms until the closing
+            // `/`
+            let mut pattern = pattern.to_string();
+            while !is_regex_end(&pattern) {
+                match terms.next() {
+                    Some(term) => {
+                        pattern.push(' ');
+                        pattern.push_str(term);
+                    }
+                    None => return Err("Unterminated regex, end it with /".into()),
+                }
+            }
+            pattern.pop();
+            let regex = Regex::new(&pattern.replace("\\/", "/"))
+                .map_err(|err| Error::from(format!("Invalid regex: {}", err)))?;
+            Filter::Regex(regex)
+        } else if let Some(path) = filter_term.strip_prefix("file:") {
+            Filter::File(path.to_string())
+        } else if let Some(size) = filter_term.strip_prefix("size:") {
+            parse_size(size)?
+        } else if let Some(namespace) = filter_term.strip_prefix("ns:") {
+            Filter::Namespace(namespace.trim_matches(':').to_string())
+        } else if let Some(property) = filter_term.strip_prefix("is:") {
+            match property {
+                "dyn" => Filter::Dynamic,
+                _ => return Err(format!("Unknown filter is:{}, expected is:dyn", property).into()),
+            }
+        } else {
+            text.push(term);
+            continue;
+        };
+        filters.push((negated, filter));
+    }
+    Ok(Query {
+        text: text.join(" "),
+        filters,
+    })
+}
+
+/// Whether `pattern` ends with a `/` that isn't escaped
+fn is_regex_end(pattern: &str) -> bool {
+    match pattern.strip_suffix('/') {
+        Some(rest) => rest.bytes().rev().take_while(|b| *b == b'\\').count() % 2 == 0,
+        None => false,
+    }
+}
+
+fn parse_size(size: &str) -> Result<Filter, Error> {
+    let (comparison, value) = if let Some(value) = size.strip_prefix(">=") {
+        (Comparison::GreaterOrEqual, value)
+    } else if let Some(value) = size.strip_prefix("<=") {
+        (Comparison::LessOrEqual, value)
+    } else if let Some(value) = size.strip_prefix('>') {
+        (Comparison::Greater, value)
+    } else if let Some(value) = size.strip_prefix('<') {
+        (Comparison::Less, value)
+    } else {
+        (Comparison::Equal, size.strip_prefix('=').unwrap_or(size))
+    };
+    match value.parse() {
+        Ok(value) => Ok(Filter::Size(comparison, value)),
+        Err(_) => Err(format!("Invalid size {}, expected e.g. size:>1000", size).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestFields {
+        name: &'static str,
+        source_file: Option<&'static str>,
+        size: u64,
+        is_dynamic: bool,
+    }
+
+    impl Fields for TestFields {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn source_file(&self) -> Option<&str> {
+            self.source_file
+        }
+
+        fn size(&self) -> u64 {
+            self.size
+        }
+
+        fn is_dynamic(&self) -> bool {
+            self.is_dynamic
+        }
+    }
+
+    const COMPACT: TestFields = TestFields {
+        name: "storage::compaction::Compactor::run(int)",
+        source_file: Some("/build/src/storage/compactor.cc"),
+        size: 2000,
+        is_dynamic: false,
+    };
+
+    const MALLOC: TestFields = TestFields {
+        name: "malloc",
+        source_file: None,
+        size: 0,
+        is_dynamic: true,
+    };
+
+    fn matches(search: &str, fields: &TestFields) -> bool {
+        parse(search).unwrap().matches(fields)
+    }
+
+    #[test]
+    fn test_parse() {
+        let query = parse("foo /a b\\/c/ bar -is:dyn").unwrap();
+        assert_eq!(query.text, "foo bar");
+        assert_eq!(query.filters.len(), 2);
+        match &query.filters[0] {
+            (false, Filter::Regex(regex)) => assert_eq!(regex.as_str(), "a b/c"),
+            filter => panic!("Unexpected filter {:?}", filter),
+        }
+        assert!(matches!(query.filters[1], (true, Filter::Dynamic)));
+
+        let query = parse("=Foo::bar size:>=10").unwrap();
+        assert_eq!(query.text, "=Foo::bar");
+        assert!(matches!(
+            query.filters[0],
+            (false, Filter::Size(Comparison::GreaterOrEqual, 10))
+        ));
+
+        assert!(parse("/foo").is_err());
+        assert!(parse("/foo(/").is_err());
+        assert!(parse("size:big").is_err());
+        assert!(parse("is:static").is_err());
+        // Names of Rust trait objects contain `dyn`
+        let query = parse("<dyn core::any::Any>::type_id").unwrap();
+        assert_eq!(query.text, "<dyn core::any::Any>::type_id");
+        assert!(!query.has_filters());
+        assert!(!parse("foo -bar").unwrap().has_filters());
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("/^storage::.*::run/", &COMPACT));
+        assert!(!matches("/^run/", &COMPACT));
+        assert!(matches("file:storage/", &COMPACT));
+        assert!(!matches("file:storage/", &MALLOC));
+        assert!(matches("-file:test", &COMPACT));
+        assert!(matches("size:>1000 size:<=2000", &COMPACT));
+        assert!(!matches("size:1000", &COMPACT));
+        assert!(matches("ns:storage", &COMPACT));
+        assert!(matches("ns:compaction::Compactor", &COMPACT));
+        assert!(!matches("ns:Compactor::run", &COMPACT));
+        assert!(!matches("ns:storage", &MALLOC));
+        assert!(matches("is:dyn", &MALLOC));
+        assert!(!matches("-is:dyn", &MALLOC));
+        assert!(matches("-is:dyn", &COMPACT));
+    }
+}
@@ -1,9 +1,12 @@
 use crate::error::Error;
 use crate::events::Event;
+use crate::line_index::{self, LocationQuery};
 use crate::program::{SymbolInfo, SymbolsGenerator};
+use crate::query;
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
-use itertools::Itertools;
+use rayon::prelude::*;
+use rayon::ThreadPool;
 use std::borrow::Cow;
 use std::cmp;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -13,6 +16,12 @@ use std::thread;
 enum SearchCommand {
     SetEmptySearchResults(Vec<(String, Option<SymbolInfo>)>),
     SetFixedItems(Vec<SymbolInfo>),
+    /// Whether searches for source locations (see `line_index::parse_query`)
+    /// find the functions at that location
+    SetLocationSearch(bool),
+    /// Replace the program symbols searched, e.g. once the program has
+    /// finished loading
+    SetSymbols(SymbolsGenerator),
     /// Counter, search view name, search string and (max) number of results.
     /// Must be sent after SetEmptySearchResults and SetFixedItems. The
     /// search will be performed on fixed items combined with
@@ -32,8 +41,15 @@ impl Searcher {
         let (command_tx, command_rx) = mpsc::channel();
         let counter = Arc::new(AtomicU64::new(0));
         let counter_copy = Arc::clone(&counter);
-        let search_thread =
-            thread::spawn(move || Searcher::search_thread(command_rx, tx, symbols, counter_copy));
+        // Created once rather than per search, since searches are run on
+        // every keystroke
+        let pool = rayon::ThreadPoolBuilder::new()
+            .thread_name(|i| format!("search-{}", i))
+            .build()
+            .unwrap();
+        let search_thread = thread::spawn(move || {
+            Searcher::search_thread(command_rx, tx, symbols, counter_copy, pool)
+        });
         Searcher {
             tx: command_tx,
             search_thread: Some(search_thread),
@@ -41,10 +57,13 @@ impl Searcher {
         }
     }
 
+    /// If `search_locations` is set, searches of the form `file:line` or
+    /// `dir/` find the functions at that source location instead.
     pub fn setup_search(
         &self,
         empty_search_results: Vec<(String, Option<SymbolInfo>)>,
         fixed_items: Vec<SymbolInfo>,
+        search_locations: bool,
     ) {
         self.inc_counter();
         self.tx
@@ -53,6 +72,15 @@ impl Searcher {
         self.tx
             .send(SearchCommand::SetFixedItems(fixed_items))
             .unwrap();
+        self.tx
+            .send(SearchCommand::SetLocationSearch(search_locations))
+            .unwrap();
+    }
+
+    /// Search `symbols` instead of the symbols passed to `new`. Doesn't affect
+    /// any in-progress search.
+    pub fn set_symbols(&self, symbols: SymbolsGenerator) {
+        self.tx.send(SearchCommand::SetSymbols(symbols)).unwrap();
     }
 
     fn inc_counter(&self) -> u64 {
@@ -78,17 +106,21 @@ impl Searcher {
     fn search_thread(
         command_rx: mpsc::Receiver<SearchCommand>,
         tx: mpsc::Sender<Event>,
-        symbols: SymbolsGenerator,
+        mut symbols: SymbolsGenerator,
         counter: Arc<AtomicU64>,
+        pool: ThreadPool,
     ) {
         let mut empty_search_results = None;
         let mut fixed_items = None;
+        let mut search_locations = false;
         for cmd in command_rx {
             match cmd {
                 SearchCommand::SetEmptySearchResults(results) => {
                     empty_search_results = Some(results)
                 }
                 SearchCommand::SetFixedItems(items) => fixed_items = Some(items),
+                SearchCommand::SetLocationSearch(enabled) => search_locations = enabled,
+                SearchCommand::SetSymbols(new_symbols) => symbols = new_symbols,
                 SearchCommand::Search(counter_val, view_name, search, n_results) => {
                     let is_cancelled_fn = || counter_val != counter.load(Ordering::Acquire);
                     if is_cancelled_fn() {
@@ -96,14 +128,34 @@ impl Searcher {
                         continue;
                     }
 
+                    let location_query = Some(&search)
+                        .filter(|_| search_locations)
+                        .and_then(|search| line_index::parse_query(search));
                     let results_opt = if search.is_empty() {
                         Some(empty_search_results.clone().unwrap())
+                    } else if let Some(query) = location_query {
+                        log::debug!("Searching for location {}", query);
+                        Some(location_results(&symbols, &query, n_results))
                     } else {
                         log::debug!("Searching for {}", search);
                         let start_time = std::time::Instant::now();
-                        let it = fixed_items.as_ref().unwrap().iter().chain(&symbols);
-                        let results_opt =
-                            rank_fn_with_cancellation(it, &search, n_results, is_cancelled_fn);
+                        let results_opt = match query::parse(&search) {
+                            Ok(query) => {
+                                let it = fixed_items.as_ref().unwrap().iter().chain(&symbols);
+                                let filter_fn = |symbol: &SymbolInfo| {
+                                    !query.has_filters() || query.matches(&symbols.fields(symbol))
+                                };
+                                rank_fn_with_cancellation(
+                                    &pool,
+                                    it,
+                                    &query.text,
+                                    n_results,
+                                    filter_fn,
+                                    is_cancelled_fn,
+                                )
+                            }
+                            Err(err) => Some(vec![(err.to_string(), None)]),
+                        };
                         match results_opt {
                             Some(_) => log::debug!(
                                 "Completed search for {}, returning {} results in {:#?}",
@@ -140,6 +192,27 @@ impl Drop for Searcher {
     }
 }
 
+fn location_results(
+    symbols: &SymbolsGenerator,
+    query: &LocationQuery,
+    n_results: usize,
+) -> Vec<(String, Option<SymbolInfo>)> {
+    match symbols.find_location(query) {
+        None => vec![(
+            "Source locations can be searched once line tables have been indexed".to_string(),
+            None,
+        )],
+        Some(results) if results.is_empty() => {
+            vec![(format!("No functions found at {}", query), None)]
+        }
+        Some(results) => results
+            .into_iter()
+            .take(n_results)
+            .map(|symbol| (symbol.to_string(), Some(symbol)))
+            .collect(),
+    }
+}
+
 pub trait Label {
     fn label(&self) -> Cow<str>;
 }
@@ -150,82 +223,199 @@ impl Label for &str {
     }
 }
 
-/// Rank matches using fuzzy search and return the top results
+impl Label for String {
+    fn label(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+/// Rank matches using fuzzy search and return the top results. Items are
+/// scored on the current thread, so this is intended for short lists.
 pub fn rank_fn<'a, T, I>(it: I, search: &str, n_results: usize) -> Vec<(String, Option<T>)>
 where
-    T: Clone + std::fmt::Display + Label + 'static,
+    T: Clone + std::fmt::Display + Label + Sync + 'static,
     I: Iterator<Item = &'a T>,
 {
-    let is_cancelled_fn = || false;
-    rank_fn_with_cancellation(it, search, n_results, is_cancelled_fn).unwrap()
+    let items: Vec<&T> = it.collect();
+    let candidates = rank_shard(
+        &items,
+        search,
+        n_results,
+        element_limit(),
+        &|_| true,
+        &|| false,
+    )
+    .unwrap();
+    to_results(candidates, n_results)
 }
 
+/// Minimum number of items scored by each task, below this the overhead of
+/// splitting up work dominates
+const MIN_SHARD_SIZE: usize = 8192;
+
 /// Rank matches using fuzzy search and return the top results, allowing for
 /// cancellation in between (since fuzzy search can take a long time). Returns
-/// `None` only when cancelled.
-fn rank_fn_with_cancellation<'a, T, I, F>(
+/// `None` only when cancelled. Only items for which `filter_fn` returns true
+/// are ranked. Items are split into shards that are scored in parallel on
+/// `pool`.
+fn rank_fn_with_cancellation<'a, T, I, P, F>(
+    pool: &ThreadPool,
     it: I,
     search: &str,
     n_results: usize,
+    filter_fn: P,
     is_cancelled_fn: F,
 ) -> Option<Vec<(String, Option<T>)>>
 where
-    T: Clone + std::fmt::Display + Label + 'static,
+    T: Clone + std::fmt::Display + Label + Sync + 'static,
     I: Iterator<Item = &'a T>,
-    F: Fn() -> bool,
+    P: Fn(&T) -> bool + Sync,
+    F: Fn() -> bool + Sync,
 {
-    // `element_limit` limits max matrix size (which is proportional to choice
-    // param's length for `fuzzy_match`) for which the fuzzy search algorithm is
-    // run. Above this size, a simpler algorithm will be run. The fuzzy
-    // algorithm gets very slow with long strings. With complex template types
-    // (which are rarely relevant with wachy in practice) this can cause
-    // unnecessary slowdown. Functions with long param list may get worse
-    // ranking from this though.
-    let element_limit = std::env::var("WACHY_FUZZY_LIMIT")
+    let items: Vec<&T> = it.collect();
+    let n_threads = pool.current_num_threads();
+    let shard_size = cmp::max(MIN_SHARD_SIZE, items.len().div_ceil(n_threads));
+    rank_shards(
+        pool,
+        &items,
+        shard_size,
+        search,
+        n_results,
+        &filter_fn,
+        &is_cancelled_fn,
+    )
+}
+
+/// `element_limit` limits max matrix size (which is proportional to choice
+/// param's length for `fuzzy_match`) for which the fuzzy search algorithm is
+/// run. Above this size, a simpler algorithm will be run. The fuzzy algorithm
+/// gets very slow with long strings. With complex template types (which are
+/// rarely relevant with wachy in practice) this can cause unnecessary
+/// slowdown. Functions with long param list may get worse ranking from this
+/// though.
+fn element_limit() -> usize {
+    std::env::var("WACHY_FUZZY_LIMIT")
         .map_err(|_| Error::from("var error"))
         .and_then(|l| l.parse::<usize>().map_err(|_| Error::from("parse error")))
-        .unwrap_or(300);
+        .unwrap_or(300)
+}
+
+/// Rank `items` in shards of `shard_size`, scored in parallel on `pool`, and
+/// merge the top results of each shard
+fn rank_shards<T, P, F>(
+    pool: &ThreadPool,
+    items: &[&T],
+    shard_size: usize,
+    search: &str,
+    n_results: usize,
+    filter_fn: &P,
+    is_cancelled_fn: &F,
+) -> Option<Vec<(String, Option<T>)>>
+where
+    T: Clone + std::fmt::Display + Label + Sync,
+    P: Fn(&T) -> bool + Sync,
+    F: Fn() -> bool + Sync,
+{
+    let element_limit = element_limit();
+    let shard_results: Vec<Option<Vec<Candidate<T>>>> = pool.install(|| {
+        items
+            .par_chunks(cmp::max(shard_size, 1))
+            .map(|shard| {
+                rank_shard(
+                    shard,
+                    search,
+                    n_results,
+                    element_limit,
+                    filter_fn,
+                    is_cancelled_fn,
+                )
+            })
+            .collect()
+    });
+
+    let mut candidates = Vec::new();
+    for shard_candidates in shard_results {
+        candidates.extend(shard_candidates?);
+    }
+    // Stable sort, so that ties are kept in the original order of items
+    candidates.sort_by(Candidate::cmp);
+    Some(to_results(candidates, n_results))
+}
+
+/// The top `n_results` of sorted `candidates`, as search results
+fn to_results<T: Clone + std::fmt::Display>(
+    candidates: Vec<Candidate<T>>,
+    n_results: usize,
+) -> Vec<(String, Option<T>)> {
+    candidates
+        .into_iter()
+        .take(n_results)
+        .map(|c| (c.value.to_string(), Some(c.value.clone())))
+        .collect()
+}
+
+/// A matching item along with its ranking information
+struct Candidate<'a, T> {
+    score: i64,
+    label_len: usize,
+    value: &'a T,
+}
+
+impl<T> Candidate<'_, T> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        match self.score.cmp(&other.score).reverse() {
+            // Prefer shorter candidates - e.g. in C++ you often have
+            // types that are stored in templatized types like
+            // unique_ptr/map etc. along with some templatized
+            // functions, but the non-templatized i.e. shortest
+            // functions are typically the ones I want to trace.
+            cmp::Ordering::Equal => self.label_len.cmp(&other.label_len),
+            o => o,
+        }
+    }
+}
+
+/// Top `n_results` matches in `shard`, best first
+fn rank_shard<'a, T, P, F>(
+    shard: &[&'a T],
+    search: &str,
+    n_results: usize,
+    element_limit: usize,
+    filter_fn: &P,
+    is_cancelled_fn: &F,
+) -> Option<Vec<Candidate<'a, T>>>
+where
+    T: Label,
+    P: Fn(&T) -> bool,
+    F: Fn() -> bool,
+{
     let matcher = SkimMatcherV2::default().element_limit(element_limit);
     let mut candidates = Vec::new();
-    for (i, val) in it.enumerate() {
+    for (i, &value) in shard.iter().enumerate() {
         if i % 32 == 0 && is_cancelled_fn() {
             return None;
         }
-        if search.starts_with("=") {
+        if !filter_fn(value) {
+            continue;
+        }
+        let label = value.label();
+        let score = if search.starts_with("=") {
             // Exact substring search
-            if val.label().contains(&search[1..]) {
-                candidates.push((1, val));
-            }
+            Some(1).filter(|_| label.contains(&search[1..]))
         } else {
-            match matcher.fuzzy_match(&*val.label(), search) {
-                Some(score) => candidates.push((score, val)),
-                _ => (),
-            }
+            matcher.fuzzy_match(&label, search)
+        };
+        if let Some(score) = score {
+            candidates.push(Candidate {
+                score,
+                label_len: label.len(),
+                value,
+            });
         }
     }
-
-    Some(
-        candidates
-            .into_iter()
-            .sorted_by(|(score1, val1), (score2, val2)| {
-                match score1.cmp(score2).reverse() {
-                    // Prefer shorter candidates - e.g. in C++ you often have
-                    // types that are stored in templatized types like
-                    // unique_ptr/map etc. along with some templatized
-                    // functions, but the non-templatized i.e. shortest
-                    // functions are typically the ones I want to trace.
-                    cmp::Ordering::Equal => {
-                        let len1 = val1.label().len();
-                        let len2 = val2.label().len();
-                        len1.cmp(&len2)
-                    }
-                    o => o,
-                }
-            })
-            .take(n_results)
-            .map(|(_, i)| (i.to_string(), Some(i.clone())))
-            .collect(),
-    )
+    candidates.sort_by(Candidate::cmp);
+    candidates.truncate(n_results);
+    Some(candidates)
 }
 
 #[cfg(test)]
@@ -236,11 +426,52 @@ mod tests {
     /// Very crude benchmark for the ranking function. Test with
     /// `cargo test --release bench -- --nocapture`.
     fn bench_rank_fn() {
-        let program = crate::program::Program::new("program".to_string()).unwrap();
+        let program = crate::program::Program::new("program".to_string(), Vec::new()).unwrap();
         println!("Loaded");
+        let pool = rayon::ThreadPoolBuilder::new().build().unwrap();
         let now = std::time::Instant::now();
-        let results = rank_fn(program.symbols_generator().into_iter(), "test", 10);
+        let symbols = program.symbols_generator();
+        let results =
+            rank_fn_with_cancellation(&pool, symbols.into_iter(), "test", 10, |_| true, || false);
         println!("{:#?}", results);
         println!("{:#?}", now.elapsed());
     }
+
+    #[test]
+    fn test_rank_shards() {
+        let items = [
+            "foo_bar", "bar", "foo", "xfoo", "baz", "foo", "afoo", "foox", "fo_o",
+        ];
+        let items: Vec<&&str> = items.iter().collect();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let expected =
+            rank_shards(&pool, &items, items.len(), "foo", 4, &|_| true, &|| false).unwrap();
+        assert_eq!(
+            expected.iter().map(|(label, _)| label).collect::<Vec<_>>(),
+            vec!["foo", "foo", "foox", "foo_bar"]
+        );
+        assert_eq!(rank_fn(items.iter().copied(), "foo", 4), expected);
+        for shard_size in 1..items.len() {
+            assert_eq!(
+                rank_shards(&pool, &items, shard_size, "foo", 4, &|_| true, &|| false).unwrap(),
+                expected
+            );
+        }
+        assert_eq!(
+            rank_shards(&pool, &items, 2, "=fo", 10, &|_| true, &|| false)
+                .unwrap()
+                .len(),
+            7
+        );
+        assert!(rank_shards(&pool, &items, 2, "foo", 4, &|_| true, &|| true).is_none());
+        let filtered =
+            rank_shards(&pool, &items, 2, "", 10, &|s| s.starts_with('f'), &|| false).unwrap();
+        assert_eq!(
+            filtered.iter().map(|(label, _)| label).collect::<Vec<_>>(),
+            vec!["foo", "foo", "foox", "fo_o", "foo_bar"]
+        );
+    }
 }
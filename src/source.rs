@@ -0,0 +1,315 @@
+use crate::error::Error;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Seek};
+use std::path::Path;
+use std::process::Command;
+
+/// A place that source files can be read from
+pub trait SourceProvider {
+    /// Read the contents of the source file at `path`. Returns None if the
+    /// provider does not have the file.
+    fn read(&self, path: &str) -> Option<String>;
+}
+
+/// Reads source files from the local filesystem. If `root` is set, only
+/// relative paths are read, and they are looked up under it.
+pub struct DirectoryProvider {
+    root: Option<String>,
+}
+
+impl DirectoryProvider {
+    pub fn new(root: Option<String>) -> Self {
+        DirectoryProvider { root }
+    }
+}
+
+impl SourceProvider for DirectoryProvider {
+    fn read(&self, path: &str) -> Option<String> {
+        let full_path = match (&self.root, Path::new(path).is_absolute()) {
+            (None, _) => path.to_string(),
+            (Some(root), false) => format!("{}/{}", root, path),
+            _ => return None,
+        };
+        std::fs::read_to_string(full_path).ok()
+    }
+}
+
+/// Reads source files (relative to the archive root) from a tarball, which
+/// may be gzip-compressed. The archive is indexed the first time a file is
+/// read from it.
+pub struct TarProvider {
+    archive: String,
+    /// Member path (without any `./` prefix) to the position and size of its
+    /// contents in the uncompressed archive. None until indexed, empty if
+    /// indexing failed.
+    index: RefCell<Option<HashMap<String, (u64, u64)>>>,
+}
+
+impl TarProvider {
+    pub fn new(archive: String) -> Self {
+        TarProvider {
+            archive,
+            index: RefCell::new(None),
+        }
+    }
+
+    /// Returns the uncompressed contents of the archive, and whether it is
+    /// compressed
+    fn open(&self) -> io::Result<(Box<dyn Read>, bool)> {
+        let mut file = io::BufReader::new(std::fs::File::open(&self.archive)?);
+        if file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+            Ok((Box::new(flate2::bufread::GzDecoder::new(file)), true))
+        } else {
+            Ok((Box::new(file), false))
+        }
+    }
+
+    fn build_index(&self) -> io::Result<HashMap<String, (u64, u64)>> {
+        let mut index = HashMap::new();
+        let mut archive = tar::Archive::new(self.open()?.0);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = entry.path()?;
+            // Archives are commonly created from `.` so members may be
+            // prefixed with `./`
+            let path = path.strip_prefix(".").unwrap_or(&path);
+            index.insert(
+                path.to_string_lossy().into_owned(),
+                (entry.raw_file_position(), entry.size()),
+            );
+        }
+        Ok(index)
+    }
+
+    fn read_member(&self, position: u64, size: u64) -> io::Result<Vec<u8>> {
+        let mut reader = match self.open()? {
+            (mut reader, true) => {
+                // Compressed streams can't be seeked
+                io::copy(&mut (&mut reader).take(position), &mut io::sink())?;
+                reader
+            }
+            (_, false) => {
+                let mut file = std::fs::File::open(&self.archive)?;
+                file.seek(io::SeekFrom::Start(position))?;
+                Box::new(file)
+            }
+        };
+        let mut contents = vec![0; size as usize];
+        reader.read_exact(&mut contents)?;
+        Ok(contents)
+    }
+}
+
+impl SourceProvider for TarProvider {
+    fn read(&self, path: &str) -> Option<String> {
+        if Path::new(path).is_absolute() {
+            return None;
+        }
+        let mut index = self.index.borrow_mut();
+        let index = index.get_or_insert_with(|| {
+            self.build_index().unwrap_or_else(|err| {
+                log::warn!("Failed to read archive {}: {}", self.archive, err);
+                HashMap::new()
+            })
+        });
+        let &(position, size) = index.get(path.trim_start_matches("./"))?;
+        match self.read_member(position, size) {
+            Ok(contents) => Some(String::from_utf8_lossy(&contents).into_owned()),
+            Err(err) => {
+                log::warn!("Failed to read {} from {}: {}", path, self.archive, err);
+                None
+            }
+        }
+    }
+}
+
+/// Reads source files (relative to the repository root) at a given revision of
+/// a local git checkout, using `git show <rev>:<path>`
+pub struct GitProvider {
+    repository: String,
+    revision: String,
+}
+
+impl GitProvider {
+    pub fn new(repository: String, revision: String) -> Self {
+        GitProvider {
+            repository,
+            revision,
+        }
+    }
+}
+
+impl SourceProvider for GitProvider {
+    fn read(&self, path: &str) -> Option<String> {
+        if Path::new(path).is_absolute() {
+            return None;
+        }
+        command_output(
+            Command::new("git")
+                .arg("-C")
+                .arg(&self.repository)
+                .arg("show")
+                .arg(format!("{}:{}", self.revision, path)),
+        )
+    }
+}
+
+fn command_output(command: &mut Command) -> Option<String> {
+    match command.output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        Ok(_) => None,
+        Err(err) => {
+            log::warn!("Failed to run {:?}: {}", command, err);
+            None
+        }
+    }
+}
+
+/// Maps source file paths from debugging symbols (i.e. where the program was
+/// compiled) to source file contents.
+pub struct Sources {
+    /// (from, to) path prefix rewrites, applied in order (first match wins)
+    source_map: Vec<(String, String)>,
+    providers: Vec<Box<dyn SourceProvider>>,
+}
+
+impl Sources {
+    /// The local filesystem is always searched first, followed by
+    /// `providers` in order.
+    pub fn new(source_map: Vec<(String, String)>, providers: Vec<Box<dyn SourceProvider>>) -> Self {
+        let mut all_providers: Vec<Box<dyn SourceProvider>> =
+            vec![Box::new(DirectoryProvider::new(None))];
+        all_providers.extend(providers);
+        Sources {
+            source_map,
+            providers: all_providers,
+        }
+    }
+
+    /// Parse a `--source-map` value of the form `FROM=TO`
+    pub fn parse_source_map(mapping: &str) -> Result<(String, String), Error> {
+        match mapping.split_once('=') {
+            Some((from, to)) if !from.is_empty() => Ok((from.to_string(), to.to_string())),
+            _ => Err(format!("Invalid source map {}, expected FROM=TO", mapping).into()),
+        }
+    }
+
+    /// Returns the lines of source file `path`, or None if no provider has
+    /// it. `comp_dir` is the compilation directory of the compilation unit
+    /// the file is from - paths are also looked up relative to it, which is
+    /// how providers with a root (source directories, tarballs, git
+    /// checkouts) find files.
+    pub fn read_lines(&self, path: &str, comp_dir: Option<&str>) -> Option<Vec<String>> {
+        let candidates = self.candidate_paths(path, comp_dir);
+        for provider in &self.providers {
+            for candidate in &candidates {
+                if let Some(contents) = provider.read(candidate) {
+                    log::info!("Reading source file {} from {}", path, candidate);
+                    return Some(contents.lines().map(String::from).collect());
+                }
+            }
+        }
+        log::info!("Source file {} not found, tried {:?}", path, candidates);
+        None
+    }
+
+    fn candidate_paths(&self, path: &str, comp_dir: Option<&str>) -> Vec<String> {
+        let mut candidates = vec![self.remap(path)];
+        let relative_path = comp_dir
+            .and_then(|dir| Path::new(path).strip_prefix(dir).ok())
+            .or_else(|| Some(Path::new(path)).filter(|p| p.is_relative()));
+        if let Some(relative_path) = relative_path {
+            candidates.push(relative_path.to_string_lossy().into_owned());
+        }
+        candidates.dedup();
+        candidates
+    }
+
+    fn remap(&self, path: &str) -> String {
+        for (from, to) in &self.source_map {
+            if let Ok(rest) = Path::new(path).strip_prefix(from) {
+                return Path::new(to).join(rest).to_string_lossy().into_owned();
+            }
+        }
+        path.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidate_paths() {
+        let sources = Sources::new(
+            vec![
+                ("/tmp/build-1234".to_string(), "/home/me/src".to_string()),
+                ("/tmp".to_string(), "/other".to_string()),
+            ],
+            Vec::new(),
+        );
+        assert_eq!(
+            sources.candidate_paths("/tmp/build-1234/src/a.cc", Some("/tmp/build-1234")),
+            vec!["/home/me/src/src/a.cc", "src/a.cc"]
+        );
+        assert_eq!(
+            sources.candidate_paths("/tmp/build-12345/a.cc", None),
+            vec!["/other/build-12345/a.cc"]
+        );
+        assert_eq!(
+            sources.candidate_paths("/usr/include/vector", Some("/tmp/build-1234")),
+            vec!["/usr/include/vector"]
+        );
+        assert_eq!(sources.candidate_paths("a.cc", None), vec!["a.cc"]);
+    }
+
+    #[test]
+    fn test_parse_source_map() {
+        assert_eq!(
+            Sources::parse_source_map("/a=/b/c").unwrap(),
+            ("/a".to_string(), "/b/c".to_string())
+        );
+        assert!(Sources::parse_source_map("/a").is_err());
+        assert!(Sources::parse_source_map("=/a").is_err());
+    }
+
+    #[test]
+    fn test_tar_provider() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in [("./src/a.cc", "int a;\n"), ("src/b.cc", "int b;\n")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        io::Write::write_all(&mut encoder, &archive).unwrap();
+        let compressed_archive = encoder.finish().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("wachy_test_tar_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, data) in [("src.tar", &archive), ("src.tar.gz", &compressed_archive)] {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            let provider = TarProvider::new(path.to_string_lossy().into_owned());
+            assert_eq!(provider.read("src/a.cc").as_deref(), Some("int a;\n"));
+            assert_eq!(provider.read("./src/b.cc").as_deref(), Some("int b;\n"));
+            assert_eq!(provider.read("src/c.cc"), None);
+            assert_eq!(provider.read("/src/a.cc"), None);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let provider = TarProvider::new(dir.join("missing.tar").to_string_lossy().into_owned());
+        assert_eq!(provider.read("src/a.cc"), None);
+    }
+}
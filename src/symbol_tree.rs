@@ -0,0 +1,220 @@
+use crate::program::{self, SymbolInfo};
+use std::collections::{BTreeMap, HashSet};
+
+/// Split a demangled function name into its enclosing scopes (namespaces and
+/// classes) followed by the function itself, which keeps its parameters, e.g.
+/// `int ns::Foo<int>::bar(char) const` -> [`ns`, `Foo<int>`, `bar(char)
+/// const`]. Rust paths are split the same way.
+pub fn split_scopes(name: &str) -> Vec<&str> {
+    let qualified_name = program::strip_return_type(program::strip_parameters(name));
+    // `qualified_name` is a substring of `name`
+    let start = qualified_name.as_ptr() as usize - name.as_ptr() as usize;
+    let name = &name[start..];
+
+    let mut scopes = Vec::new();
+    let mut scope_start = 0;
+    let mut depth = 0;
+    let bytes = qualified_name.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            // Operators contain brackets and `::` can't appear after them
+            b'o' if depth == 0
+                && i == scope_start
+                && qualified_name[i..].starts_with("operator") =>
+            {
+                break
+            }
+            b'<' | b'(' | b'[' | b'{' => depth += 1,
+            b'>' | b')' | b']' | b'}' => depth -= 1,
+            b':' if depth == 0 && bytes.get(i + 1) == Some(&b':') => {
+                scopes.push(&name[scope_start..i]);
+                scope_start = i + 2;
+                i += 1;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    scopes.push(&name[scope_start..]);
+    scopes
+}
+
+/// What a row of `SymbolTree::rows` refers to
+#[derive(Clone, Debug)]
+pub enum TreeItem {
+    /// Path of scope names from the root
+    Scope(Vec<String>),
+    Function(SymbolInfo),
+}
+
+#[derive(Clone, Debug)]
+pub struct TreeRow {
+    pub depth: usize,
+    pub label: String,
+    pub item: TreeItem,
+}
+
+/// Functions of a program grouped by namespace and class
+#[derive(Default)]
+pub struct SymbolTree {
+    scopes: BTreeMap<String, SymbolTree>,
+    /// Functions directly in this scope, by name relative to it
+    functions: Vec<(String, SymbolInfo)>,
+    /// Number of functions in this scope, including nested scopes
+    n_functions: usize,
+}
+
+impl SymbolTree {
+    pub fn new<'a>(symbols: impl Iterator<Item = &'a SymbolInfo>) -> SymbolTree {
+        let mut root = SymbolTree::default();
+        for symbol in symbols {
+            let scopes = split_scopes(symbol.as_ref());
+            let (function, scopes) = scopes.split_last().unwrap();
+            let mut tree = &mut root;
+            tree.n_functions += 1;
+            for scope in scopes {
+                tree = tree.scopes.entry(scope.to_string()).or_default();
+                tree.n_functions += 1;
+            }
+            tree.functions.push((function.to_string(), symbol.clone()));
+        }
+        root.sort();
+        root
+    }
+
+    fn sort(&mut self) {
+        self.functions
+            .sort_by(|(name1, _), (name2, _)| name1.cmp(name2));
+        for scope in self.scopes.values_mut() {
+            scope.sort();
+        }
+    }
+
+    /// Rows to display, where scopes in `expanded` (see `TreeItem::Scope`)
+    /// show their contents below them. Scopes are listed before functions.
+    pub fn rows(&self, expanded: &HashSet<Vec<String>>) -> Vec<TreeRow> {
+        let mut rows = Vec::new();
+        self.add_rows(&mut Vec::new(), expanded, &mut rows);
+        rows
+    }
+
+    fn add_rows(
+        &self,
+        path: &mut Vec<String>,
+        expanded: &HashSet<Vec<String>>,
+        rows: &mut Vec<TreeRow>,
+    ) {
+        let depth = path.len();
+        for (name, scope) in &self.scopes {
+            path.push(name.clone());
+            let is_expanded = expanded.contains(path);
+            rows.push(TreeRow {
+                depth,
+                label: format!(
+                    "{} {} ({})",
+                    if is_expanded { '-' } else { '+' },
+                    name,
+                    scope.n_functions
+                ),
+                item: TreeItem::Scope(path.clone()),
+            });
+            if is_expanded {
+                scope.add_rows(path, expanded, rows);
+            }
+            path.pop();
+        }
+        for (name, symbol) in &self.functions {
+            rows.push(TreeRow {
+                depth,
+                label: format!("  {}", name),
+                item: TreeItem::Function(symbol.clone()),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_scopes() {
+        assert_eq!(
+            split_scopes("int ns::Foo<a::b>::bar(char) const"),
+            vec!["ns", "Foo<a::b>", "bar(char) const"]
+        );
+        assert_eq!(
+            split_scopes("(anonymous namespace)::parse(char const*)"),
+            vec!["(anonymous namespace)", "parse(char const*)"]
+        );
+        assert_eq!(
+            split_scopes("ns::Foo::operator<<(int)"),
+            vec!["ns", "Foo", "operator<<(int)"]
+        );
+        assert_eq!(
+            split_scopes("std::vector<int>::{lambda(int)#1}::operator()(int) const"),
+            vec![
+                "std",
+                "vector<int>",
+                "{lambda(int)#1}",
+                "operator()(int) const"
+            ]
+        );
+        assert_eq!(
+            split_scopes("wachy::program::Program::new"),
+            vec!["wachy", "program", "Program", "new"]
+        );
+        assert_eq!(split_scopes("main"), vec!["main"]);
+    }
+
+    #[test]
+    fn test_rows() {
+        let symbols = [
+            SymbolInfo::new("ns::Foo::b()", 1),
+            SymbolInfo::new("main", 2),
+            SymbolInfo::new("ns::Foo::a()", 3),
+            SymbolInfo::new("ns::helper()", 4),
+            SymbolInfo::new("ns::Bar::c()", 5),
+            SymbolInfo::new("Baz::d()", 6),
+        ];
+        let tree = SymbolTree::new(symbols.iter());
+        let labels = |expanded: &[&[&str]]| {
+            let expanded: HashSet<Vec<String>> = expanded
+                .iter()
+                .map(|path| path.iter().map(|s| s.to_string()).collect())
+                .collect();
+            tree.rows(&expanded)
+                .into_iter()
+                .map(|row| format!("{}{}", "  ".repeat(row.depth), row.label))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(labels(&[]), vec!["+ Baz (1)", "+ ns (4)", "  main"]);
+        // Scopes are listed before functions, each sorted by name
+        assert_eq!(
+            labels(&[&["ns"], &["ns", "Foo"]]),
+            vec![
+                "+ Baz (1)",
+                "- ns (4)",
+                "  + Bar (1)",
+                "  - Foo (2)",
+                "      a()",
+                "      b()",
+                "    helper()",
+                "  main",
+            ]
+        );
+        // Nested scopes are only shown if all enclosing scopes are expanded
+        assert_eq!(
+            labels(&[&["ns", "Foo"]]),
+            vec!["+ Baz (1)", "+ ns (4)", "  main"]
+        );
+
+        let expanded: HashSet<Vec<String>> = vec![vec!["ns".to_string()]].into_iter().collect();
+        let rows = tree.rows(&expanded);
+        assert!(matches!(&rows[1].item, TreeItem::Scope(path) if path == &["ns"]));
+        assert!(matches!(&rows[2].item, TreeItem::Scope(path) if path == &["ns", "Bar"]));
+        assert!(matches!(&rows[4].item, TreeItem::Function(symbol) if symbol.name.address == 4));
+    }
+}
@@ -5,7 +5,7 @@ use crate::bpftrace_compiler::Expression::Printf;
 use crate::bpftrace_compiler::{self, Block, BlockType, Expression};
 use crate::error::Error;
 use crate::events::{Event, TraceCumulative, TraceInfo, TraceInfoMode};
-use crate::program::FunctionName;
+use crate::program::{self, FunctionName};
 use std::collections::HashMap;
 use std::process::Command;
 use std::process::Stdio;
@@ -19,7 +19,13 @@ use std::{fmt, iter};
 /// bpftrace programs.
 pub struct TraceStack {
     counter: AtomicU64,
+    /// Path of the traced program. Frames may be in other object files (shared
+    /// libraries), but breakdown functions are always in this one.
     program_path: String,
+    /// Name of the instruction pointer register in bpftrace, which depends on
+    /// the program's architecture
+    ip_register: &'static str,
+    target: TraceTarget,
     /// Stack of functions being traced
     stack: Mutex<Frames>,
 }
@@ -35,6 +41,18 @@ pub struct Frames {
     tx: Sender<Event>,
 }
 
+/// Processes in which the traced functions are probed
+#[derive(Clone, Debug)]
+pub enum TraceTarget {
+    /// All processes running the program
+    All,
+    /// A single, already running process
+    Pid(u32),
+    /// Command (program and arguments) launched by bpftrace, which is
+    /// relaunched every time bpftrace is rerun.
+    Command(Vec<String>),
+}
+
 #[derive(Copy, Clone)]
 pub enum TraceMode {
     /// Trace latency per traced line in current view
@@ -43,12 +61,20 @@ pub enum TraceMode {
     Histogram,
     /// Trace amount of time spent in each of the specified nest functions
     Breakdown,
+    /// Count the targets of the indirect calls on the given line of the
+    /// current function
+    IndirectTargets { line: u32 },
 }
 
 #[derive(Debug, Clone)]
 pub struct FrameInfo {
     function: FunctionName,
+    /// Path of the object file (program or shared library) containing
+    /// `function`
+    object_path: String,
     source_file: String,
+    /// Compilation directory of the compilation unit containing `function`
+    comp_dir: Option<String>,
     source_line: u32,
     /// Map from source line numbers to call functions on that line
     line_to_callsites: HashMap<u32, Vec<CallInstruction>>,
@@ -63,6 +89,20 @@ pub struct FrameInfo {
     /// bpftrace filter to apply on function exit (uretprobe). Necessary to
     /// support things like `$duration` which have to be evaluated on return.
     ret_filter: Option<String>,
+    /// Other functions traced as part of this frame (e.g. other
+    /// instantiations of the same template), see `aggregate`.
+    aggregated: Vec<AggregatedFunction>,
+}
+
+/// A function that is traced along with the main function of a frame, sharing
+/// its depth bookkeeping and results
+#[derive(Debug, Clone)]
+struct AggregatedFunction {
+    function: FunctionName,
+    /// None if the function's calls don't line up with those of the main
+    /// function, in which case it only adds to the frame's function-level
+    /// latency and frequency
+    line_to_callsites: Option<HashMap<u32, Vec<CallInstruction>>>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -71,15 +111,19 @@ pub enum InstructionType {
     DynamicSymbol(FunctionName),
     /// Function being called, if it's a hardcoded function
     Function(FunctionName),
-    /// Register being called. Note: should be a bpftrace register
+    /// Register being called. Note: should be a bpftrace register (e.g.
     /// https://github.com/iovisor/bpftrace/blob/master/src/arch/x86_64.cpp,
-    /// which notably does not have E or R prefixes.
+    /// which notably does not have E or R prefixes).
     /// Second field represents displacement within register.
     Register(String, Option<i64>),
     /// Manually specified start/end offset for tracing
     Manual,
     /// Unknown function call - doesn't correspond to any symbols
     Unknown,
+    /// Jump to another function (the wrapped instruction type) at the end of
+    /// this one. The callee returns directly to our caller, so the call ends
+    /// when this function returns.
+    TailCall(Box<InstructionType>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -89,6 +133,21 @@ pub struct CallInstruction {
     /// Size of instruction
     length: u32,
     pub instruction: InstructionType,
+    /// If the call is in inlined code, the functions it was inlined through
+    /// (outermost first)
+    inline_chain: Vec<String>,
+    /// If set, the call is in this separate part of the function and
+    /// `relative_ip` is relative to the start of the part
+    part: Option<FunctionPart>,
+}
+
+/// A part of a function that the compiler split off into a separate symbol,
+/// e.g. GCC's `foo.cold` for unlikely code
+#[derive(Debug, Clone, PartialEq)]
+struct FunctionPart {
+    function: FunctionName,
+    /// Start address of the part relative to the start of the function
+    offset: i64,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -99,28 +158,108 @@ struct TraceOutput {
     histogram: Option<String>,
     // Map from (stringified) index to (duration, count)
     breakdown: Option<HashMap<String, (u64, u64)>>,
+    // Printed bpftrace map of (relative ip, target - ip) to count
+    targets: Option<String>,
 }
 
 impl FrameInfo {
     pub fn new(
         function: FunctionName,
+        object_path: String,
         source_file: String,
+        comp_dir: Option<String>,
         source_line: u32,
         line_to_callsites: HashMap<u32, Vec<CallInstruction>>,
         unattached_callsites: Vec<CallInstruction>,
     ) -> FrameInfo {
         FrameInfo {
             function,
+            object_path,
             source_file,
+            comp_dir,
             source_line,
             line_to_callsites,
             unattached_callsites,
             traced_callsites: HashMap::new(),
             filter: None,
             ret_filter: None,
+            aggregated: Vec::new(),
         }
     }
 
+    /// Trace `other`'s function as part of this frame. If it is defined in
+    /// the same source file and its calls line up with this frame's calls
+    /// (see `calls_line_up`), they are matched up by source line and position
+    /// within the line and true is returned. Otherwise the function only adds
+    /// to the frame's function-level latency and frequency.
+    pub fn aggregate(&mut self, other: FrameInfo) -> bool {
+        assert_eq!(self.object_path, other.object_path);
+        let lines_up = self.source_file == other.source_file && self.calls_line_up(&other);
+        self.aggregated.push(AggregatedFunction {
+            function: other.function,
+            line_to_callsites: Some(other.line_to_callsites).filter(|_| lines_up),
+        });
+        lines_up
+    }
+
+    /// Trace `function` as part of this frame, only adding to the frame's
+    /// function-level latency and frequency
+    pub fn aggregate_function(&mut self, function: FunctionName) {
+        self.aggregated.push(AggregatedFunction {
+            function,
+            line_to_callsites: None,
+        });
+    }
+
+    /// Whether `other`'s function has the same calls as this frame's function
+    /// on each line, in the same order, e.g. because it is another
+    /// instantiation of the same template.
+    fn calls_line_up(&self, other: &FrameInfo) -> bool {
+        self.line_to_callsites.len() == other.line_to_callsites.len()
+            && self.line_to_callsites.iter().all(|(line, callsites)| {
+                match other.line_to_callsites.get(line) {
+                    Some(other_callsites) => {
+                        callsites.len() == other_callsites.len()
+                            && callsites
+                                .iter()
+                                .zip(other_callsites)
+                                .all(|(c, o)| c.instruction.is_equivalent(&o.instruction))
+                    }
+                    None => false,
+                }
+            })
+    }
+
+    /// All functions traced in this frame
+    fn functions(&self) -> Vec<FunctionName> {
+        iter::once(self.function)
+            .chain(self.aggregated.iter().map(|a| a.function))
+            .collect()
+    }
+
+    /// `callsite` on `line`, along with the corresponding callsites in each
+    /// aggregated function whose calls line up. Manually specified and
+    /// unattached callsites only exist in the main function.
+    fn matching_callsites(
+        &self,
+        line: u32,
+        callsite: &CallInstruction,
+    ) -> Vec<(FunctionName, CallInstruction)> {
+        let mut callsites = vec![(self.function, callsite.clone())];
+        let index = self
+            .line_to_callsites
+            .get(&line)
+            .and_then(|cis| cis.iter().position(|ci| ci == callsite));
+        if let Some(index) = index {
+            // Calls of aggregated functions line up with ours
+            callsites.extend(self.aggregated.iter().filter_map(|a| {
+                let line_to_callsites = a.line_to_callsites.as_ref()?;
+                Some((a.function, line_to_callsites[&line][index].clone()))
+            }));
+        }
+        callsites
+    }
+
     /// Source line numbers that contain a call instruction
     pub fn called_lines(&self) -> Vec<u32> {
         self.line_to_callsites.keys().map(|l| *l).collect()
@@ -130,6 +269,10 @@ impl FrameInfo {
         &self.source_file
     }
 
+    pub fn get_comp_dir(&self) -> Option<&str> {
+        self.comp_dir.as_deref()
+    }
+
     pub fn get_source_line(&self) -> u32 {
         self.source_line
     }
@@ -149,6 +292,8 @@ impl CallInstruction {
             relative_ip,
             length: length as u32,
             instruction: InstructionType::DynamicSymbol(function),
+            inline_chain: Vec::new(),
+            part: None,
         }
     }
 
@@ -157,6 +302,8 @@ impl CallInstruction {
             relative_ip,
             length: length as u32,
             instruction: InstructionType::Function(function),
+            inline_chain: Vec::new(),
+            part: None,
         }
     }
 
@@ -170,6 +317,8 @@ impl CallInstruction {
             relative_ip,
             length: length as u32,
             instruction: InstructionType::Register(register, displacement),
+            inline_chain: Vec::new(),
+            part: None,
         }
     }
 
@@ -178,21 +327,64 @@ impl CallInstruction {
             relative_ip,
             length,
             instruction: InstructionType::Manual,
+            inline_chain: Vec::new(),
+            part: None,
+        }
+    }
+
+    pub fn tail_call(relative_ip: u32, length: u8, target: InstructionType) -> CallInstruction {
+        CallInstruction {
+            relative_ip,
+            length: length as u32,
+            instruction: InstructionType::TailCall(Box::new(target)),
+            inline_chain: Vec::new(),
+            part: None,
         }
     }
 
+    pub fn is_tail_call(&self) -> bool {
+        matches!(self.instruction, InstructionType::TailCall(_))
+    }
+
+    pub fn set_inline_chain(&mut self, inline_chain: Vec<String>) {
+        self.inline_chain = inline_chain;
+    }
+
+    /// Mark the call as being in separate part `function` of the function,
+    /// which starts `offset` bytes from the start of the function.
+    /// `relative_ip` must be relative to the start of the part.
+    pub fn set_part(&mut self, function: FunctionName, offset: i64) {
+        self.part = Some(FunctionPart { function, offset });
+    }
+
+    /// The function to probe the call instruction relative to, where
+    /// `function` is the traced function
+    fn probe_function(&self, function: FunctionName) -> FunctionName {
+        self.part.as_ref().map_or(function, |part| part.function)
+    }
+
+    /// IP of call instruction relative to the start of the traced function
+    fn function_offset(&self) -> i64 {
+        self.part.as_ref().map_or(0, |part| part.offset) + self.relative_ip as i64
+    }
+
     pub fn unknown(relative_ip: u32, length: u8) -> CallInstruction {
         CallInstruction {
             relative_ip,
             length: length as u32,
             instruction: InstructionType::Unknown,
+            inline_chain: Vec::new(),
+            part: None,
         }
     }
 }
 
 impl fmt::Display for CallInstruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_fmt(format_args!("{}: ", self.relative_ip))?;
+        match &self.part {
+            Some(part) => f.write_fmt(format_args!("{}+{}: ", part.function, self.relative_ip))?,
+            None => f.write_fmt(format_args!("{}: ", self.relative_ip))?,
+        }
         let i = &self.instruction;
         match i {
             InstructionType::DynamicSymbol(_) => f.write_fmt(format_args!("(D) {}", i)),
@@ -204,7 +396,15 @@ impl fmt::Display for CallInstruction {
                 self.relative_ip + self.length
             )),
             InstructionType::Unknown => f.write_fmt(format_args!("{}", i)),
+            InstructionType::TailCall(target) => f.write_fmt(format_args!("(T) {}", target)),
+        }?;
+        if !self.inline_chain.is_empty() {
+            f.write_fmt(format_args!(
+                " (inlined in {})",
+                self.inline_chain.join(" -> ")
+            ))?;
         }
+        Ok(())
     }
 }
 
@@ -219,12 +419,75 @@ impl fmt::Display for InstructionType {
             },
             InstructionType::Manual => f.write_str("(Manual)"),
             InstructionType::Unknown => f.write_str("(UNKNOWN)"),
+            InstructionType::TailCall(target) => target.fmt(f),
+        }
+    }
+}
+
+impl InstructionType {
+    /// Whether this could be the same call as `other` in a different
+    /// instantiation of a template, i.e. both call the same function or
+    /// instantiations of the same function template.
+    fn is_equivalent(&self, other: &InstructionType) -> bool {
+        use InstructionType::*;
+        let generic_name = |function: &FunctionName| {
+            let name = function.to_string();
+            program::strip_template_arguments(program::strip_return_type(
+                program::strip_parameters(&name),
+            ))
+        };
+        match (self, other) {
+            (Function(f1), Function(f2)) | (DynamicSymbol(f1), DynamicSymbol(f2)) => {
+                f1 == f2 || generic_name(f1) == generic_name(f2)
+            }
+            (Register(_, _), Register(_, _)) | (Unknown, Unknown) => true,
+            (TailCall(t1), TailCall(t2)) => t1.is_equivalent(t2),
+            _ => false,
+        }
+    }
+
+    /// bpftrace expression evaluating to the (runtime) address of the function
+    /// being called, when probed at the call instruction. Only available for
+    /// register calls. `length` is the length of the call instruction, and
+    /// `ip_register` the name of the instruction pointer register.
+    fn bpftrace_target_expr(&self, length: u32, ip_register: &str) -> Option<String> {
+        match self {
+            InstructionType::Register(register, None) => Some(format!("reg(\"{}\")", register)),
+            // IP-relative addresses are relative to the next instruction
+            InstructionType::Register(register, Some(displacement)) if register == ip_register => {
+                Some(format!(
+                    "*(uint64*)(reg(\"{}\") + {})",
+                    ip_register,
+                    length as i64 + displacement
+                ))
+            }
+            InstructionType::Register(register, Some(displacement)) => Some(format!(
+                "*(uint64*)(reg(\"{}\") + {})",
+                register, displacement
+            )),
+            _ => None,
+        }
+    }
+
+    /// Function being called, if it is known
+    pub fn get_function(&self) -> Option<FunctionName> {
+        match self {
+            InstructionType::DynamicSymbol(function) => Some(*function),
+            InstructionType::Function(function) => Some(*function),
+            InstructionType::TailCall(target) => target.get_function(),
+            _ => None,
         }
     }
 }
 
 impl TraceStack {
-    pub fn new(program_path: String, frame: FrameInfo, tx: Sender<Event>) -> TraceStack {
+    pub fn new(
+        program_path: String,
+        ip_register: &'static str,
+        target: TraceTarget,
+        frame: FrameInfo,
+        tx: Sender<Event>,
+    ) -> TraceStack {
         let stack = Mutex::new(Frames {
             mode: TraceMode::Line,
             breakdown_functions: Vec::new(),
@@ -234,6 +497,8 @@ impl TraceStack {
         TraceStack {
             counter: AtomicU64::new(0),
             program_path,
+            ip_register,
+            target,
             stack,
         }
     }
@@ -243,6 +508,20 @@ impl TraceStack {
         guard.frames.last().unwrap().function
     }
 
+    pub fn get_current_object_path(&self) -> String {
+        let guard = self.stack.lock().unwrap();
+        guard.frames.last().unwrap().object_path.clone()
+    }
+
+    pub fn get_current_source_file(&self) -> String {
+        let guard = self.stack.lock().unwrap();
+        guard.frames.last().unwrap().source_file.clone()
+    }
+
+    pub fn get_target(&self) -> &TraceTarget {
+        &self.target
+    }
+
     pub fn get_callsites(&self, line: u32) -> Vec<CallInstruction> {
         let guard = self.stack.lock().unwrap();
         let callsites = guard
@@ -305,6 +584,15 @@ impl TraceStack {
         self.command_modified(guard);
     }
 
+    /// Insert `frame` below the current (top) frame, so that the current
+    /// function is only traced when it is called from `frame`'s function.
+    pub fn insert_below_top(&self, frame: FrameInfo) {
+        let mut guard = self.stack.lock().unwrap();
+        let index = guard.frames.len() - 1;
+        guard.frames.insert(index, frame);
+        self.command_modified(guard);
+    }
+
     /// Pops the current frame, if it is not the last one. Returns the new top
     /// of the frame (note this is different from typical stack behavior).
     pub fn pop(&self) -> Option<FrameInfo> {
@@ -356,7 +644,8 @@ impl TraceStack {
         *frame_filter = Some(filter);
         // Run bpftrace in dry run mode to ensure filter compiles
         let output = bpftrace_cmd()
-            .args(&["-d", "-e", &self.get_bpftrace_expr_locked(&guard).0])
+            .arg("-d")
+            .args(self.get_bpftrace_args_locked(&guard).0)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .output()
@@ -386,15 +675,31 @@ impl TraceStack {
         guard.breakdown_functions.clone()
     }
 
-    /// Get appropriate bpftrace expression for current state, along with
-    /// current counter value.
+    /// Get arguments to run bpftrace with for current state, including the
+    /// bpftrace expression, along with current counter value.
     /// Panics if called with empty stack
-    pub fn get_bpftrace_expr(&self) -> (String, u64) {
+    pub fn get_bpftrace_args(&self) -> (Vec<String>, u64) {
         let guard = self.stack.lock().unwrap();
-        self.get_bpftrace_expr_locked(&guard)
+        self.get_bpftrace_args_locked(&guard)
+    }
+
+    fn get_bpftrace_args_locked(&self, guard: &MutexGuard<Frames>) -> (Vec<String>, u64) {
+        let program = self.get_bpftrace_program_locked(guard);
+        let mut args = self.target.bpftrace_args();
+        if program.requires_unsafe() {
+            args.push("--unsafe".to_string());
+        }
+        let expr = program.compile();
+        log::debug!("Current bpftrace expression: {}", expr);
+        args.extend(vec!["-e".to_string(), expr]);
+        // Since we hold lock we know counter won't change
+        (args, self.counter.load(Ordering::Relaxed))
     }
 
-    fn get_bpftrace_expr_locked(&self, guard: &MutexGuard<Frames>) -> (String, u64) {
+    fn get_bpftrace_program_locked(
+        &self,
+        guard: &MutexGuard<Frames>,
+    ) -> bpftrace_compiler::BpftraceProgram {
         // General approach to codegen:
         // 1. Maintain `@depth` on function entry and exit to ensure we are
         //    following the trace stack.
@@ -424,11 +729,18 @@ impl TraceStack {
             ],
         ));
 
-        let depth_condition =
-            |depth: usize| -> Option<String> { Some(format!("@depth[tid] == {}", depth)) };
+        // Every uprobe is guarded by a depth condition, so restricting to the
+        // target process there is sufficient.
+        let pid_condition = match self.target.pid_filter() {
+            Some(filter) => format!("{} && ", filter),
+            None => String::new(),
+        };
+        let depth_condition = |depth: usize| -> Option<String> {
+            Some(format!("{}@depth[tid] == {}", pid_condition, depth))
+        };
         for (i, frame) in frames.iter().take(frames.len() - 1).enumerate() {
             program.add(Block::new(
-                Uprobe(frame.function),
+                Uprobe(frame.object_path.clone(), frame.functions()),
                 depth_condition(i),
                 TraceStack::add_user_filter(
                     &frame.filter,
@@ -440,7 +752,7 @@ impl TraceStack {
                 ),
             ));
             program.add(Block::new(
-                Uretprobe(frame.function),
+                Uretprobe(frame.object_path.clone(), frame.functions()),
                 depth_condition(i + 1),
                 TraceStack::add_user_filter(
                     &frame.ret_filter,
@@ -463,9 +775,11 @@ impl TraceStack {
         let frame_depth = frames.len() - 1;
         let line = last_frame.source_line;
         let function = last_frame.function;
+        let functions = last_frame.functions();
+        let object_path = &last_frame.object_path;
 
         program.add(Block::new(
-            Uprobe(function),
+            Uprobe(object_path.clone(), functions.clone()),
             depth_condition(frame_depth),
             TraceStack::add_user_filter(
                 &last_frame.filter,
@@ -477,49 +791,81 @@ impl TraceStack {
             ),
         ));
 
-        match guard.mode {
+        let print_exprs = match guard.mode {
             TraceMode::Line => {
-                program.add(Block::new(
-                    Uretprobe(function),
-                    depth_condition(frame_depth + 1),
-                    TraceStack::add_user_filter(
-                        &last_frame.ret_filter,
-                        true,
-                        vec![
+                // Tail calls return directly to our caller, so they end when
+                // this function returns.
+                let mut ret_exprs: Vec<Expression> = last_frame
+                    .traced_callsites
+                    .iter()
+                    .filter(|(_, callsite)| callsite.is_tail_call())
+                    .map(|(&line, _)| Expression::If {
+                        condition: format!("@start{}[tid]", line),
+                        body: vec![
                             format!(
                                 "@duration_tmp{line}[tid] += (nsecs - @start{line}[tid])",
                                 line = line
-                            ),
-                            format!("$duration = @duration_tmp{}[tid]", line),
-                            format!("@count_tmp{}[tid] += 1", line),
-                            format!("delete(@start{}[tid])", line),
-                            format!("@depth[tid] = {}", frame_depth),
+                            )
+                            .into(),
+                            format!("@count_tmp{}[tid] += 1", line).into(),
+                            format!("delete(@start{}[tid])", line).into(),
                         ],
-                    ),
+                    })
+                    .collect();
+                ret_exprs.extend(TraceStack::add_user_filter(
+                    &last_frame.ret_filter,
+                    true,
+                    vec![
+                        format!(
+                            "@duration_tmp{line}[tid] += (nsecs - @start{line}[tid])",
+                            line = line
+                        ),
+                        format!("$duration = @duration_tmp{}[tid]", line),
+                        format!("@count_tmp{}[tid] += 1", line),
+                        format!("delete(@start{}[tid])", line),
+                        format!("@depth[tid] = {}", frame_depth),
+                    ],
+                ));
+                program.add(Block::new(
+                    Uretprobe(object_path.clone(), functions.clone()),
+                    depth_condition(frame_depth + 1),
+                    ret_exprs,
                 ));
 
-                for (&line, callsite) in &last_frame.traced_callsites {
-                    program.add(Block::new(
-                        UprobeOffset(function, callsite.relative_ip),
-                        depth_condition(frame_depth + 1),
-                        vec![format!("@start{}[tid] = nsecs", line)],
-                    ));
-                    // Ensure the tracepoint at the end of the call is only
-                    // triggered if we traced the start.
-                    let call_done_condition = depth_condition(frame_depth + 1)
-                        .map(|c| c + &format!(" && @start{}[tid]", line));
-                    program.add(Block::new(
-                        UprobeOffset(function, callsite.relative_ip + callsite.length as u32),
-                        call_done_condition,
-                        vec![
-                            format!(
-                                "@duration_tmp{line}[tid] += (nsecs - @start{line}[tid])",
-                                line = line
+                for (&line, traced_callsite) in &last_frame.traced_callsites {
+                    for (function, callsite) in last_frame.matching_callsites(line, traced_callsite)
+                    {
+                        let function = callsite.probe_function(function);
+                        program.add(Block::new(
+                            UprobeOffset(object_path.clone(), function, callsite.relative_ip),
+                            depth_condition(frame_depth + 1),
+                            vec![format!("@start{}[tid] = nsecs", line)],
+                        ));
+                        if callsite.is_tail_call() {
+                            // Handled in uretprobe above
+                            continue;
+                        }
+                        // Ensure the tracepoint at the end of the call is only
+                        // triggered if we traced the start.
+                        let call_done_condition = depth_condition(frame_depth + 1)
+                            .map(|c| c + &format!(" && @start{}[tid]", line));
+                        program.add(Block::new(
+                            UprobeOffset(
+                                object_path.clone(),
+                                function,
+                                callsite.relative_ip + callsite.length,
                             ),
-                            format!("@count_tmp{}[tid] += 1", line),
-                            format!("delete(@start{}[tid])", line),
-                        ],
-                    ));
+                            call_done_condition,
+                            vec![
+                                format!(
+                                    "@duration_tmp{line}[tid] += (nsecs - @start{line}[tid])",
+                                    line = line
+                                ),
+                                format!("@count_tmp{}[tid] += 1", line),
+                                format!("delete(@start{}[tid])", line),
+                            ],
+                        ));
+                    }
                 }
 
                 let mut print_exprs = vec![Printf {
@@ -540,15 +886,11 @@ impl TraceStack {
                     format: r#"}}\n"#.to_string(),
                     args: Vec::new(),
                 });
-                program.add(Block::new(
-                    BlockType::Interval { rate_seconds: 1 },
-                    None,
-                    print_exprs,
-                ));
+                print_exprs
             }
             TraceMode::Histogram => {
                 program.add(Block::new(
-                    Uretprobe(last_frame.function),
+                    Uretprobe(object_path.clone(), functions.clone()),
                     depth_condition(frame_depth + 1),
                     TraceStack::add_user_filter(
                         &last_frame.ret_filter,
@@ -573,11 +915,61 @@ impl TraceStack {
                         args: Vec::new(),
                     },
                 ];
+                print_exprs
+            }
+            TraceMode::IndirectTargets { line: target_line } => {
+                // Ret filters are not supported here, targets are counted
+                // directly. Targets are keyed by call instruction, so only
+                // the main function of an aggregate frame is probed.
                 program.add(Block::new(
-                    BlockType::Interval { rate_seconds: 1 },
-                    None,
-                    print_exprs,
+                    Uretprobe(object_path.clone(), functions.clone()),
+                    depth_condition(frame_depth + 1),
+                    vec![
+                        format!("delete(@start{}[tid])", line),
+                        format!("@depth[tid] = {}", frame_depth),
+                    ],
                 ));
+                let callsites = last_frame
+                    .line_to_callsites
+                    .get(&target_line)
+                    .map(|cis| cis.as_slice())
+                    .unwrap_or_default();
+                for callsite in callsites {
+                    if let Some(target) = callsite
+                        .instruction
+                        .bpftrace_target_expr(callsite.length, self.ip_register)
+                    {
+                        // Key by offset from the call instruction so that the
+                        // target can be mapped back to a function regardless
+                        // of where the object is loaded.
+                        program.add(Block::new(
+                            UprobeOffset(
+                                object_path.clone(),
+                                callsite.probe_function(function),
+                                callsite.relative_ip,
+                            ),
+                            depth_condition(frame_depth + 1),
+                            vec![format!(
+                                "@targets[{}, (int64)({}) - (int64)reg(\"{}\")] = count()",
+                                callsite.function_offset(),
+                                target,
+                                self.ip_register
+                            )],
+                        ));
+                    }
+                }
+
+                vec![
+                    Printf {
+                        format: r#"{"time": %d, "targets": ""#.to_string(),
+                        args: vec!["(nsecs - @start_time) / 1000000000".to_string()],
+                    },
+                    Expression::Print("@targets".to_string()),
+                    Printf {
+                        format: r#""}\n"#.to_string(),
+                        args: Vec::new(),
+                    },
+                ]
             }
             TraceMode::Breakdown => {
                 // Need `+=` here for most variables rather than `=` because we
@@ -587,7 +979,7 @@ impl TraceStack {
                 // multiple times but still have to accumulate time for all of
                 // them.
                 program.add(Block::new(
-                    Uretprobe(function),
+                    Uretprobe(object_path.clone(), functions.clone()),
                     depth_condition(frame_depth + 1),
                     TraceStack::add_user_filter(
                         &last_frame.ret_filter,
@@ -603,7 +995,7 @@ impl TraceStack {
                 ));
                 for (i, &function) in guard.breakdown_functions.iter().enumerate() {
                     program.add(Block::new(
-                        Uprobe(function),
+                        Uprobe(self.program_path.clone(), vec![function]),
                         depth_condition(frame_depth + 1),
                         vec![format!("@start_breakdown{}[tid] = nsecs", i)],
                     ));
@@ -616,7 +1008,7 @@ impl TraceStack {
                     let ret_condition = depth_condition(frame_depth + 1)
                         .map(|c| c + &format!(" && @start_breakdown{}[tid]", i));
                     program.add(Block::new(
-                        Uretprobe(function),
+                        Uretprobe(self.program_path.clone(), vec![function]),
                         ret_condition,
                         vec![
                             format!(
@@ -659,13 +1051,19 @@ impl TraceStack {
                     format: r#"}}\n"#.to_string(),
                     args: Vec::new(),
                 });
-                program.add(Block::new(
-                    BlockType::Interval { rate_seconds: 1 },
-                    None,
-                    print_exprs,
-                ));
+                print_exprs
             }
         };
+        if let TraceTarget::Command(_) = self.target {
+            // The launched program may exit before the first interval, so also
+            // print the final results on exit.
+            program.add(Block::new(BlockType::End, None, print_exprs.clone()));
+        }
+        program.add(Block::new(
+            BlockType::Interval { rate_seconds: 1 },
+            None,
+            print_exprs,
+        ));
 
         // Add expression to commit `_tmp` vars to their final version when
         // appropriate and always clear. This should happen in the first/topmost
@@ -674,7 +1072,7 @@ impl TraceStack {
         let last_retprobe = program
             .iter_mut()
             .find(|b| match b.get_type() {
-                Uretprobe(_) => true,
+                Uretprobe(_, _) => true,
                 _ => false,
             })
             .unwrap();
@@ -758,12 +1156,12 @@ impl TraceStack {
                         .collect(),
                 );
             }
+            TraceMode::IndirectTargets { .. } => {
+                last_retprobe.add("delete(@matched_retfilters[tid])".into());
+            }
         };
 
-        let expr = program.compile(&self.program_path);
-        log::debug!("Current bpftrace expression: {}", expr);
-        // Since we hold lock we know counter won't change
-        (expr, self.counter.load(Ordering::Relaxed))
+        program
     }
 
     fn add_user_filter<T>(
@@ -827,6 +1225,8 @@ impl TraceStack {
             )
         } else if let Some(histogram) = info.histogram {
             TraceInfoMode::Histogram(histogram)
+        } else if let Some(targets) = info.targets {
+            TraceInfoMode::IndirectTargets(TraceStack::parse_targets(&targets))
         } else {
             let breakdown = info.breakdown.unwrap();
             TraceInfoMode::Breakdown {
@@ -847,11 +1247,239 @@ impl TraceStack {
         })
     }
 
+    /// Parse printed `@targets` map, with lines of the form
+    /// `@targets[<relative ip>, <target offset>]: <count>`
+    fn parse_targets(targets: &str) -> Vec<(i64, i64, u64)> {
+        targets
+            .lines()
+            .filter_map(|line| {
+                let (key, count) = line.trim().strip_prefix("@targets[")?.split_once("]: ")?;
+                let (relative_ip, offset) = key.split_once(", ")?;
+                Some((
+                    relative_ip.parse().ok()?,
+                    offset.parse().ok()?,
+                    count.parse().ok()?,
+                ))
+            })
+            .collect()
+    }
+
     pub fn is_counter_current(&self, counter: u64) -> bool {
         counter == self.counter.load(Ordering::Acquire)
     }
 }
 
+impl TraceTarget {
+    /// Additional arguments that need to be passed to bpftrace
+    pub fn bpftrace_args(&self) -> Vec<String> {
+        match self {
+            TraceTarget::All | TraceTarget::Pid(_) => Vec::new(),
+            TraceTarget::Command(command) => vec![
+                "-c".to_string(),
+                command.iter().map(|arg| shell_quote(arg)).join(" "),
+            ],
+        }
+    }
+
+    /// bpftrace filter restricting probes to the target process, if any
+    fn pid_filter(&self) -> Option<String> {
+        match self {
+            TraceTarget::All => None,
+            TraceTarget::Pid(pid) => Some(format!("pid == {}", pid)),
+            TraceTarget::Command(_) => Some("pid == cpid".to_string()),
+        }
+    }
+}
+
+/// Quote `arg` (if required) so that it is parsed as a single word by
+/// bpftrace's `-c`.
+fn shell_quote(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r#"'\''"#))
+    }
+}
+
 pub fn bpftrace_cmd() -> Command {
     Command::new("bpftrace")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn frame_info(
+        function: FunctionName,
+        line_to_callsites: Vec<(u32, Vec<CallInstruction>)>,
+    ) -> FrameInfo {
+        FrameInfo::new(
+            function,
+            "/bin/prog".to_string(),
+            "prog.cc".to_string(),
+            None,
+            10,
+            line_to_callsites.into_iter().collect(),
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn test_aggregate() {
+        // Instantiations of `template <typename T> int run(T)`
+        let run_int = FunctionName::new("_Z3runIiEiT_", 0x1000);
+        let run_long = FunctionName::new("_Z3runIlEiT_", 0x2000);
+        let run_char = FunctionName::new("_Z3runIcEiT_", 0x3000);
+        let hash_int = FunctionName::new("_Z4hashIiEmT_", 0x4000);
+        let hash_long = FunctionName::new("_Z4hashIlEmT_", 0x5000);
+        let log = FunctionName::new("_Z3logv", 0x6000);
+        let int_calls = vec![
+            CallInstruction::function(0x10, 5, hash_int),
+            CallInstruction::function(0x20, 5, log),
+        ];
+        let mut frame = frame_info(run_int, vec![(11, int_calls.clone())]);
+        // Calls instantiations of the same templates, so the calls line up
+        let long_frame = frame_info(
+            run_long,
+            vec![(
+                11,
+                vec![
+                    CallInstruction::function(0x14, 5, hash_long),
+                    CallInstruction::function(0x28, 5, log),
+                ],
+            )],
+        );
+        // The compiler ordered the calls differently
+        let char_frame = frame_info(
+            run_char,
+            vec![(
+                11,
+                vec![
+                    CallInstruction::function(0x10, 5, log),
+                    CallInstruction::function(0x20, 5, hash_int),
+                ],
+            )],
+        );
+        assert!(frame.calls_line_up(&long_frame));
+        assert!(!frame.calls_line_up(&char_frame));
+        assert!(!frame.calls_line_up(&frame_info(run_char, vec![(11, vec![])])));
+        assert!(!frame.calls_line_up(&frame_info(run_char, vec![(12, int_calls.clone())])));
+
+        assert!(frame.aggregate(long_frame));
+        assert_eq!(
+            frame.matching_callsites(11, &int_calls[1]),
+            vec![
+                (run_int, int_calls[1].clone()),
+                (run_long, CallInstruction::function(0x28, 5, log)),
+            ]
+        );
+        let manual = CallInstruction::manual(0x30, 8);
+        assert_eq!(
+            frame.matching_callsites(11, &manual),
+            vec![(run_int, manual.clone())]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_overloads() {
+        // Overloads `Foo::bar(int)` and `Foo::bar(char)` have different bodies
+        let bar_int = FunctionName::new("_ZN3Foo3barEi", 0x1000);
+        let bar_char = FunctionName::new("_ZN3Foo3barEc", 0x2000);
+        let bar_long = FunctionName::new("_ZN3Foo3barEl", 0x3000);
+        let callee = FunctionName::new("callee", 0x4000);
+        let call = CallInstruction::function(0x10, 5, callee);
+        let mut frame = frame_info(bar_int, vec![(11, vec![call.clone()])]);
+        let char_frame = frame_info(
+            bar_char,
+            vec![(21, vec![CallInstruction::function(0x18, 5, callee)])],
+        );
+        assert!(!frame.aggregate(char_frame));
+        frame.aggregate_function(bar_long);
+        assert_eq!(frame.functions(), vec![bar_int, bar_char, bar_long]);
+        assert_eq!(
+            frame.matching_callsites(11, &call),
+            vec![(bar_int, call.clone())]
+        );
+
+        let (tx, _rx) = mpsc::channel();
+        let stack = TraceStack::new("/bin/prog".to_string(), "ip", TraceTarget::All, frame, tx);
+        stack.add_callsite(11, call);
+        let (args, _) = stack.get_bpftrace_args();
+        let expr = args.last().unwrap();
+        // All overloads are probed for the function's latency and frequency
+        let probes = "uprobe:/bin/prog:_ZN3Foo3barEi, uprobe:/bin/prog:_ZN3Foo3barEc, uprobe:/bin/prog:_ZN3Foo3barEl ";
+        assert!(expr.contains(probes));
+        assert!(expr.contains(&probes.replace("uprobe", "uretprobe")));
+        // Only the first overload's call is traced
+        assert!(expr.contains("uprobe:/bin/prog:_ZN3Foo3barEi+16 "));
+        assert!(!expr.contains("_ZN3Foo3barEc+"));
+    }
+
+    #[test]
+    fn test_unsafe_probes() {
+        let function = FunctionName::new("outer", 0x1000);
+        let callee = FunctionName::new("callee", 0x2000);
+        let call = CallInstruction::function(0x10, 5, callee);
+        let stack_args = |function: FunctionName| {
+            let frame = frame_info(function, vec![(11, vec![call.clone()])]);
+            let (tx, _rx) = mpsc::channel();
+            let stack = TraceStack::new("/bin/prog".to_string(), "ip", TraceTarget::All, frame, tx);
+            stack.add_callsite(11, call.clone());
+            stack.get_bpftrace_args().0
+        };
+        assert_eq!(stack_args(function)[0], "-e");
+        // bpftrace only attaches to addresses of functions that aren't in the
+        // symbol table in unsafe mode
+        let args = stack_args(function.without_symbol());
+        assert_eq!(args[..2], ["--unsafe", "-e"]);
+        assert!(args[2].contains("uprobe:/bin/prog:0x1010 "));
+    }
+
+    #[test]
+    fn test_tail_call_codegen() {
+        let function = FunctionName::new("outer", 0x1000);
+        let callee = FunctionName::new("callee", 0x2000);
+        let tail_callee = FunctionName::new("tail_callee", 0x3000);
+        let call = CallInstruction::function(0x10, 5, callee);
+        let tail_call = CallInstruction::tail_call(0x20, 5, InstructionType::Function(tail_callee));
+        let frame = frame_info(
+            function,
+            vec![(11, vec![call.clone()]), (12, vec![tail_call.clone()])],
+        );
+        let (tx, _rx) = mpsc::channel();
+        let stack = TraceStack::new("/bin/prog".to_string(), "ip", TraceTarget::All, frame, tx);
+        stack.add_callsite(11, call);
+        stack.add_callsite(12, tail_call);
+        let (args, _) = stack.get_bpftrace_args();
+        let expr = args.last().unwrap();
+
+        // Both calls start when the call instruction is reached
+        assert!(expr
+            .contains("uprobe:/bin/prog:outer+16 /@depth[tid] == 1/ { @start11[tid] = nsecs; }"));
+        assert!(expr
+            .contains("uprobe:/bin/prog:outer+32 /@depth[tid] == 1/ { @start12[tid] = nsecs; }"));
+        // A regular call ends after the call instruction, but the tail call
+        // never returns there
+        assert!(expr.contains("uprobe:/bin/prog:outer+21 "));
+        assert!(!expr.contains("uprobe:/bin/prog:outer+37 "));
+        // The tail call ends when the callee returns to our caller, i.e. when
+        // the uretprobe of the traced function fires. It must be accounted
+        // for before the function's own duration is.
+        let uretprobe = expr
+            .split(" uretprobe:")
+            .nth(1)
+            .and_then(|s| s.split(" uprobe:").next())
+            .unwrap();
+        assert!(uretprobe.starts_with("/bin/prog:outer /@depth[tid] == 1/ { "));
+        let tail_call_end = "if (@start12[tid]) { @duration_tmp12[tid] += (nsecs - @start12[tid]); @count_tmp12[tid] += 1; delete(@start12[tid]); }";
+        let tail_call_pos = uretprobe.find(tail_call_end).unwrap();
+        let function_end_pos = uretprobe.find("@duration_tmp10[tid] +=").unwrap();
+        assert!(tail_call_pos < function_end_pos);
+        // The tail call's results are committed along with the other lines
+        assert!(uretprobe
+            .contains("@duration12 += @duration_tmp12[tid]; @count12 += @count_tmp12[tid];"));
+        assert!(!uretprobe.contains("@start11[tid])"));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::error::Error;
 use crate::events::Event;
-use crate::trace_structs::{bpftrace_cmd, TraceStack};
+use crate::trace_structs::{bpftrace_cmd, TraceStack, TraceTarget};
 use std::io::{BufRead, Read};
 use std::process::Stdio;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -116,9 +116,12 @@ impl TraceCommandHandler {
         self.output_processor.take().map(|t| t.join());
         self.is_killing.store(false, Ordering::Release);
 
-        let (expr, counter) = self.trace_stack.get_bpftrace_expr();
+        let (args, counter) = self.trace_stack.get_bpftrace_args();
+        let target = self.trace_stack.get_target();
         let mut program = bpftrace_cmd()
-            .args(&["-e", &expr])
+            .args(&args)
+            // Don't let a launched command read from the terminal
+            .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
@@ -127,7 +130,18 @@ impl TraceCommandHandler {
         log::trace!("bpftrace program_id: {:?}", self.program_id);
         let tx = self.data_tx.clone();
         let is_killing_copy = Arc::clone(&self.is_killing);
+        let is_command = matches!(target, TraceTarget::Command(_));
         self.output_processor = Some(thread::spawn(move || {
+            // A launched command shares bpftrace's stderr and may write a lot
+            // to it, so drain it concurrently to avoid blocking on a full pipe.
+            let mut stderr_pipe = program.stderr.take().unwrap();
+            let stderr_reader = thread::spawn(move || {
+                let mut stderr = String::new();
+                if let Err(err) = stderr_pipe.read_to_string(&mut stderr) {
+                    log::error!("Failed to read bpftrace stderr: {:?}", err);
+                }
+                stderr
+            });
             let stdout = program.stdout.as_mut().unwrap();
             let stdout_reader = std::io::BufReader::new(stdout);
             log::trace!("Starting!");
@@ -145,8 +159,9 @@ impl TraceCommandHandler {
                 if !json_buf.is_empty() {
                     json_buf += "\n";
                     json_buf += &line;
-                } else if !line.starts_with("{") {
-                    // bpftrace prints all maps on exit, which we want to ignore
+                } else if !line.starts_with(r#"{"time": "#) {
+                    // bpftrace prints all maps on exit, which we want to ignore.
+                    // A launched command also shares our stdout.
                     continue;
                 } else {
                     json_buf = line;
@@ -172,21 +187,26 @@ impl TraceCommandHandler {
             }
             let status = program.wait().unwrap();
             log::trace!("Done, status: {}!", status);
-            let mut stderr = String::new();
-            match program.stderr.unwrap().read_to_string(&mut stderr) {
-                Err(err) => log::error!("Failed to read bpftrace stderr: {:?}", err),
-                _ => (),
-            }
-            if !status.success() && !is_killing_copy.load(Ordering::Acquire) {
+            let stderr = stderr_reader.join().unwrap();
+            let is_killing = is_killing_copy.load(Ordering::Acquire);
+            if !status.success() && !is_killing {
                 tx.send(Event::FatalTraceError {
                     error_message: format!(
                         "bpftrace command '{}' failed, status: {:?}, stderr:\n{}",
-                        expr, status, stderr
+                        args.join(" "),
+                        status,
+                        stderr
                     ),
                 })
                 .unwrap();
-            } else if !stderr.is_empty() {
-                log::info!("bpftrace stderr:\n{}", stderr);
+            } else {
+                if !stderr.is_empty() {
+                    log::info!("bpftrace stderr:\n{}", stderr);
+                }
+                if is_command && !is_killing {
+                    // bpftrace exits once the launched command does
+                    tx.send(Event::TracedProgramExited { counter }).unwrap();
+                }
             }
         }));
     }
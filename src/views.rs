@@ -1,4 +1,6 @@
+use crate::program::SymbolInfo;
 use crate::search;
+use crate::symbol_tree::{SymbolTree, TreeItem};
 use core::cmp::Ordering;
 use cursive::theme::{BaseColor, Color, ColorStyle};
 use cursive::utils::markup::StyledString;
@@ -8,6 +10,8 @@ use cursive::views::{
     TextView,
 };
 use cursive::Cursive;
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::rc::Rc;
 
 #[derive(Clone, Copy, Debug)]
@@ -154,38 +158,55 @@ pub fn set_source_view(
     selected_line: u32,
     marked_lines: Vec<u32>,
 ) {
+    let mut items = source_items(source_code, marked_lines);
+    if let Some(pending) = items.get_mut(selected_line as usize - 1) {
+        pending.latency = TraceState::Pending;
+        pending.frequency = TraceState::Pending;
+    }
+    // Set this twice - once before to prevent out of bounds, second time to
+    // ensure the table actually scrolls to the right place.
+    sview.set_selected_row(selected_line as usize - 1);
+    sview.set_items(items);
+    sview.set_selected_row(selected_line as usize - 2);
+    sview.set_selected_row(selected_line as usize - 1);
+}
+
+fn source_items(source_code: Vec<String>, marked_lines: Vec<u32>) -> Vec<source_view::Item> {
     use source_view::Item;
     let mut items: Vec<Item> = source_code
         .into_iter()
         .enumerate()
-        .map(|(i, line)| {
-            let pending = i as u32 == selected_line - 1;
-            Item {
-                latency: if pending {
-                    TraceState::Pending
-                } else {
-                    TraceState::Untraced
-                },
-                frequency: if pending {
-                    TraceState::Pending
-                } else {
-                    TraceState::Untraced
-                },
-                line_number: i as u32 + 1,
-                line,
-                marked: false,
-            }
+        .map(|(i, line)| Item {
+            latency: TraceState::Untraced,
+            frequency: TraceState::Untraced,
+            line_number: i as u32 + 1,
+            line,
+            marked: false,
         })
         .collect();
     for line in marked_lines {
         items.get_mut(line as usize - 1).unwrap().marked = true;
     }
-    // Set this twice - once before to prevent out of bounds, second time to
-    // ensure the table actually scrolls to the right place.
-    sview.set_selected_row(selected_line as usize - 1);
-    sview.set_items(items);
-    sview.set_selected_row(selected_line as usize - 2);
+    items
+}
+
+/// Source view of a whole source file, without tracing info. `submit_fn` is
+/// called with the line number when a line is submitted.
+pub fn new_file_view<F>(
+    title: &str,
+    source_code: Vec<String>,
+    marked_lines: Vec<u32>,
+    submit_fn: F,
+) -> Dialog
+where
+    F: Fn(&mut Cursive, u32) + 'static,
+{
+    let selected_line = marked_lines.first().copied().unwrap_or(1);
+    let mut sview = new_source_view();
+    sview.set_items(source_items(source_code, marked_lines));
     sview.set_selected_row(selected_line as usize - 1);
+    sview.set_on_submit(move |siv: &mut Cursive, row: usize, _| submit_fn(siv, row as u32 + 1));
+    Dialog::around(sview.full_screen()).title(title)
 }
 
 pub type FooterView = PaddedView<Layer<TextView>>;
@@ -302,7 +323,7 @@ where
 /// Convenience wrapper for new_search_view with results searched using search::rank_fn
 pub fn new_simple_search_view<T, G>(title: &str, items: Vec<T>, submit_fn: G) -> SearchView
 where
-    T: Clone + std::fmt::Display + search::Label + 'static,
+    T: Clone + std::fmt::Display + search::Label + Sync + 'static,
     G: Fn(&mut Cursive, &T) + 'static,
 {
     let initial_results = search::rank_fn(items.iter(), "", usize::MAX);
@@ -317,6 +338,67 @@ where
     )
 }
 
+const TREE_VIEW_HEIGHT: usize = 20;
+
+/// Expandable tree of the functions in `tree`. Submitting a scope expands (or
+/// collapses) it, submitting a function closes the view and calls
+/// `submit_fn`.
+pub fn new_symbol_tree_view<G>(title: &str, tree: Rc<SymbolTree>, submit_fn: G) -> SearchView
+where
+    G: Fn(&mut Cursive, &SymbolInfo) + 'static,
+{
+    let name = format!("tree_{}", title);
+    let name_copy = name.clone();
+    let expanded = RefCell::new(HashSet::new());
+
+    let mut select_view = SelectView::<TreeItem>::new();
+    set_tree_rows(&mut select_view, &tree, &expanded.borrow());
+    let select_view = ScrollView::new(
+        select_view
+            .on_submit(move |siv: &mut Cursive, item: &TreeItem| match item {
+                TreeItem::Scope(path) => {
+                    {
+                        let mut expanded = expanded.borrow_mut();
+                        if !expanded.remove(path) {
+                            expanded.insert(path.clone());
+                        }
+                    }
+                    siv.call_on_name(&name_copy, |view: &mut SelectView<TreeItem>| {
+                        let selected = view.selected_id();
+                        set_tree_rows(view, &tree, &expanded.borrow());
+                        if let Some(selected) = selected {
+                            // Rows above the selected scope are unchanged
+                            let _ = view.set_selection(selected);
+                        }
+                    });
+                }
+                TreeItem::Function(symbol) => {
+                    siv.pop_layer();
+                    submit_fn(siv, symbol);
+                }
+            })
+            .with_name(&name)
+            .full_width(),
+    )
+    .scroll_x(true)
+    .fixed_size((SEARCH_VIEW_WIDTH, TREE_VIEW_HEIGHT));
+
+    Dialog::around(select_view)
+        .title(title)
+        .fixed_width(SEARCH_VIEW_WIDTH + 4)
+}
+
+fn set_tree_rows(
+    view: &mut SelectView<TreeItem>,
+    tree: &SymbolTree,
+    expanded: &HashSet<Vec<String>>,
+) {
+    view.clear();
+    for row in tree.rows(expanded) {
+        view.add_item(format!("{}{}", "  ".repeat(row.depth), row.label), row.item);
+    }
+}
+
 /// Simple dialog with single confirmation button that closes it
 pub fn new_dialog(text: &str) -> Dialog {
     Dialog::text(text).button("OK", |siv| {
@@ -326,6 +408,20 @@ pub fn new_dialog(text: &str) -> Dialog {
 
 pub type TextDialogView = TextView;
 
+const PROGRESS_VIEW_NAME: &str = "progress";
+
+/// Dialog without buttons showing the progress of a long running operation,
+/// see `set_progress_view`
+pub fn new_progress_view(title: &str, text: &str) -> Dialog {
+    Dialog::around(TextView::new(text).with_name(PROGRESS_VIEW_NAME)).title(title)
+}
+
+pub fn set_progress_view(siv: &mut Cursive, text: &str) {
+    siv.call_on_name(PROGRESS_VIEW_NAME, |view: &mut TextView| {
+        view.set_content(text)
+    });
+}
+
 pub fn new_text_dialog_view<F>(text: &str, name: &str, close_fn: F) -> Dialog
 where
     F: 'static + Fn(&mut Cursive),
@@ -348,6 +444,14 @@ pub fn is_text_dialog_view(view: &Box<dyn cursive::View>, name: &str) -> bool {
     false
 }
 
+/// Check if this is a view created by `new_search_view` with the given `title`
+pub fn is_search_view(view: &dyn cursive::View, title: &str) -> bool {
+    match view.downcast_ref::<SearchView>() {
+        Some(search_view) => search_view.get_inner().get_title() == title,
+        None => false,
+    }
+}
+
 pub fn new_quit_dialog(text: &str) -> Dialog {
     Dialog::text(text)
         .button("Quit", Cursive::quit)
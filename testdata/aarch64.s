@@ -0,0 +1,21 @@
+// Fixture for AArch64 tests, see build.sh
+
+        .text
+        .globl local_func
+        .type local_func, %function
+local_func:
+        mov w0, #2
+        ret
+        .size local_func, .-local_func
+
+        .globl _start
+        .type _start, %function
+_start:
+        stp x29, x30, [sp, #-16]!
+        bl local_func
+        bl ext_func
+        ldr x8, =local_func
+        blr x8
+        ldp x29, x30, [sp], #16
+        b ext_func
+        .size _start, .-_start
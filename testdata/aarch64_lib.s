@@ -0,0 +1,9 @@
+// Fixture for AArch64 tests, see build.sh
+
+        .text
+        .globl ext_func
+        .type ext_func, %function
+ext_func:
+        add w0, w0, #1
+        ret
+        .size ext_func, .-ext_func
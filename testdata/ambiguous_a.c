@@ -0,0 +1,7 @@
+// Fixture for ambiguous function name tests, see build.sh
+
+__attribute__((noinline)) static int helper(int x) { return x + 1; }
+
+int other(int x);
+
+int main(int argc, char **argv) { return helper(argc) + other(argc); }
@@ -0,0 +1,5 @@
+// Fixture for ambiguous function name tests, see build.sh
+
+__attribute__((noinline)) static int helper(int x) { return x * 2; }
+
+int other(int x) { return helper(x); }
@@ -0,0 +1,54 @@
+#!/bin/sh
+# Regenerates the test fixture binaries in this directory. These are checked in
+# so that tests don't depend on compilers or binutils for other architectures.
+set -e
+cd "$(dirname "$0")"
+
+# Stripped x86-64 program whose function symbols are only in MiniDebugInfo
+# (https://sourceware.org/gdb/onlinedocs/gdb/MiniDebugInfo.html). The line
+# tables are kept.
+gcc -g -O1 -o minidebuginfo.full minidebuginfo.c
+nm minidebuginfo.full --format=posix --defined-only |
+    awk '{ if ($2 == "T" || $2 == "t") print $1 }' >minidebuginfo.syms
+objcopy --only-keep-debug minidebuginfo.full minidebuginfo.debug
+objcopy -S --remove-section .comment --keep-symbols=minidebuginfo.syms \
+    minidebuginfo.debug minidebuginfo.debug
+xz --force minidebuginfo.debug
+strip --strip-all --keep-section=.debug_line --keep-section=.debug_info \
+    --keep-section=.debug_abbrev --keep-section=.debug_str \
+    --keep-section=.debug_line_str -o minidebuginfo minidebuginfo.full
+objcopy --add-section .gnu_debugdata=minidebuginfo.debug.xz minidebuginfo
+rm minidebuginfo.full minidebuginfo.syms minidebuginfo.debug.xz
+
+# x86-64 program with a call inlined from a helper in the same file
+gcc -g -O2 -o inline inline.c
+
+# x86-64 C++ programs with secondary vtables, position independent (vtable
+# entries are relocated) or not
+g++ -g -O1 -o vtables vtables.cpp
+g++ -g -O1 -no-pie -o vtables_no_pie vtables.cpp
+
+# x86-64 program with a tail call
+gcc -g -O2 -o tailcall tailcall.c
+
+# x86-64 program with unlikely code split off into a `.cold` part
+gcc -g -O2 -freorder-blocks-and-partition -o cold cold.c
+
+# x86-64 program with static functions of the same name in two files
+gcc -g -O2 -o ambiguous ambiguous_a.c ambiguous_b.c
+
+# x86-64 program with a function removed from the symbol table
+gcc -g -O1 -o debuginfo_only debuginfo_only.c
+objcopy --strip-symbol=hidden debuginfo_only
+
+# AArch64 program calling a function in a shared library through the PLT.
+# Assembled with llvm-mc and linked with lld (e.g. rustup's rust-lld), since
+# they support all targets.
+LLVM_MC=${LLVM_MC:-llvm-mc}
+LD_LLD=${LD_LLD:-ld.lld}
+$LLVM_MC -triple=aarch64-linux-gnu -filetype=obj -o aarch64_lib.o aarch64_lib.s
+$LD_LLD -shared -soname libaarch64_ext.so -o libaarch64_ext.so aarch64_lib.o
+$LLVM_MC -triple=aarch64-linux-gnu -filetype=obj -g -o aarch64.o aarch64.s
+$LD_LLD -o aarch64 aarch64.o libaarch64_ext.so \
+    --dynamic-linker /lib/ld-linux-aarch64.so.1
+rm aarch64_lib.o libaarch64_ext.so aarch64.o
@@ -0,0 +1,13 @@
+// Fixture for function part tests, see build.sh
+
+// Calls to a cold function are moved to a `.cold` part of the caller
+__attribute__((noinline, cold)) int report(int x) { return x * 3 + 1; }
+
+__attribute__((noinline)) int check(int x) {
+  if (__builtin_expect(x > 100, 0)) {
+    return report(x) + report(x + 1);
+  }
+  return x + 1;
+}
+
+int main(int argc, char **argv) { return check(argc); }
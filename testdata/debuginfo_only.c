@@ -0,0 +1,7 @@
+// Fixture for functions that are only described in debug info, see build.sh
+
+__attribute__((noinline)) int hidden(int x) { return x + 1; }
+
+__attribute__((noinline)) int visible(int x) { return hidden(x) * 2; }
+
+int main(int argc, char **argv) { return visible(argc); }
@@ -0,0 +1,13 @@
+// Fixture for inlined call tests, see build.sh
+
+__attribute__((noinline)) int leaf(int x) { return x + 1; }
+
+static inline int helper(int x) {
+  return leaf(x) * 2;
+}
+
+__attribute__((noinline)) int outer(int x) {
+  return helper(x) + 3;
+}
+
+int main(int argc, char **argv) { return outer(argc); }
@@ -0,0 +1,7 @@
+// Fixture for MiniDebugInfo tests, see build.sh
+
+__attribute__((noinline)) static int mini_debug_info_function(int x) {
+  return x * 3;
+}
+
+int main(int argc, char **argv) { return mini_debug_info_function(argc); }
@@ -0,0 +1,10 @@
+// Fixture for callers index tests, see build.sh
+
+__attribute__((noinline)) int leaf(int x) { return x * 3 + 1; }
+
+// Compiled to a jump to leaf
+__attribute__((noinline)) int tail(int x) { return leaf(x + 1); }
+
+__attribute__((noinline)) int caller(int x) { return leaf(x) + 1; }
+
+int main(int argc, char **argv) { return tail(argc) + caller(argc); }
@@ -0,0 +1,37 @@
+// Fixture for virtual call candidate tests, with secondary vtables (multiple
+// inheritance) and a virtual base, see build.sh
+
+struct A {
+  virtual int a();
+};
+struct B {
+  virtual int b1();
+  virtual int b2();
+};
+struct C : A, B {
+  int a() override;
+  int b2() override;
+};
+struct V {
+  virtual int v();
+};
+struct D : A, virtual V {
+  int v() override;
+};
+
+int A::a() { return 1; }
+int B::b1() { return 2; }
+int B::b2() { return 3; }
+int C::a() { return 4; }
+int C::b2() { return 5; }
+int V::v() { return 6; }
+int D::v() { return 7; }
+
+int call_b2(B *b) { return b->b2(); }
+int call_v(V *v) { return v->v(); }
+
+int main() {
+  C c;
+  D d;
+  return call_b2(&c) + call_v(&d);
+}